@@ -0,0 +1,60 @@
+//! Compares decoding every fetched word, reusing predecoded instructions and running
+//! translated basic blocks. Throughput is in executed LC-3 instructions.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lc3_vm::{assembler::assemble, engine::Engine, memory::Memory, registers::Registers, vm::Vm};
+
+/// Nested loops mixing arithmetic, memory accesses, branches and subroutine calls.
+const KERNEL: &str = "
+        .ORIG x3000
+        LD R1, OUTER_N
+OUTER   LD R2, INNER_N
+INNER   ADD R3, R3, R2
+        AND R4, R3, #15
+        LEA R5, BUF
+        STR R4, R5, #0
+        LDR R6, R5, #0
+        JSR NOOP
+        ADD R2, R2, #-1
+        BRp INNER
+        ADD R1, R1, #-1
+        BRp OUTER
+        HALT
+NOOP    RET
+OUTER_N .FILL #100
+INNER_N .FILL #1000
+BUF     .BLKW 1
+        .END
+";
+
+fn vm(predecode: bool, engine: Engine) -> Vm {
+    let assembly = assemble(KERNEL).unwrap();
+    let mut memory = Memory::default();
+    memory.set_predecode(predecode);
+    memory.load(&assembly.image);
+
+    let mut vm = Vm::new(Registers::default(), memory);
+    vm.set_engine(engine);
+    vm
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut counted = vm(true, Engine::Interpreter);
+    counted.run().unwrap();
+    let instructions = counted.instructions();
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(instructions));
+    group.sample_size(20);
+    for (name, predecode, engine) in [
+        ("decode every cycle", false, Engine::Interpreter),
+        ("predecoded", true, Engine::Interpreter),
+        ("blocks", true, Engine::Blocks),
+    ] {
+        group.bench_function(name, |b| b.iter(|| vm(predecode, engine).run().unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
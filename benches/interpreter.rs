@@ -0,0 +1,215 @@
+//! Instructions per second for `Vm::run` on the bundled games, played headlessly with
+//! scripted keys, and on compute-heavy kernels. Throughput is in executed LC-3 instructions.
+
+use std::{io, path::Path};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lc3_vm::{
+    assembler::assemble, console::Scripted, image::Image, memory::Memory, registers::Registers,
+    vm::Vm,
+};
+
+/// Multiplies every pair of numbers from 1 to 40 by shift-and-add, summing the products.
+const MULTIPLY: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        LD R1, N
+OUTER   LD R2, N
+INNER   AND R3, R3, #0
+        ADD R4, R1, #0
+        ADD R5, R2, #0
+        AND R6, R6, #0
+        ADD R6, R6, #1
+BIT     AND R7, R5, R6
+        BRz SKIP
+        ADD R3, R3, R4
+SKIP    ADD R4, R4, R4
+        ADD R6, R6, R6
+        BRnp BIT
+        ADD R0, R0, R3
+        ADD R2, R2, #-1
+        BRp INNER
+        ADD R1, R1, #-1
+        BRp OUTER
+        HALT
+N       .FILL #40
+        .END
+";
+
+/// Counts the primes below 4000 with the sieve of Eratosthenes.
+const SIEVE: &str = "
+        .ORIG x3000
+        LEA R1, FLAGS
+        AND R0, R0, #0
+        AND R2, R2, #0
+        ADD R2, R2, #2
+OUTER   LD R3, N
+        NOT R4, R2
+        ADD R4, R4, #1
+        ADD R3, R3, R4
+        BRnz DONE
+        ADD R5, R1, R2
+        LDR R6, R5, #0
+        BRnp NEXT
+        ADD R0, R0, #1
+        ADD R5, R2, R2
+INNER   LD R3, N
+        NOT R6, R5
+        ADD R6, R6, #1
+        ADD R3, R3, R6
+        BRnz NEXT
+        ADD R6, R1, R5
+        STR R2, R6, #0
+        ADD R5, R5, R2
+        BRnzp INNER
+NEXT    ADD R2, R2, #1
+        BRnzp OUTER
+DONE    HALT
+N       .FILL #4000
+FLAGS   .BLKW #4000
+        .END
+";
+
+/// Bubble sorts 256 words from descending into ascending order.
+const SORT: &str = "
+        .ORIG x3000
+        LEA R1, DATA
+        LD R2, COUNT
+INIT    STR R2, R1, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp INIT
+        LD R2, COUNT
+PASS    ADD R2, R2, #-1
+        BRnz DONE
+        LEA R1, DATA
+        ADD R3, R2, #0
+CMP     LDR R4, R1, #0
+        LDR R5, R1, #1
+        NOT R0, R5
+        ADD R0, R0, #1
+        ADD R0, R4, R0
+        BRnz KEEP
+        STR R5, R1, #0
+        STR R4, R1, #1
+KEEP    ADD R1, R1, #1
+        ADD R3, R3, #-1
+        BRp CMP
+        BRnzp PASS
+DONE    HALT
+COUNT   .FILL #256
+DATA    .BLKW #256
+        .END
+";
+
+/// Computes the 20th Fibonacci number recursively, saving registers on a stack.
+const FIBONACCI: &str = "
+        .ORIG x3000
+        LD R6, STACK
+        LD R0, ARG
+        JSR FIB
+        HALT
+FIB     ADD R6, R6, #-3
+        STR R7, R6, #0
+        STR R1, R6, #1
+        STR R2, R6, #2
+        ADD R1, R0, #-2
+        BRn RETURN
+        ADD R1, R0, #0
+        ADD R0, R1, #-1
+        JSR FIB
+        ADD R2, R0, #0
+        ADD R0, R1, #-2
+        JSR FIB
+        ADD R0, R0, R2
+RETURN  LDR R2, R6, #2
+        LDR R1, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #3
+        RET
+STACK   .FILL xF000
+ARG     .FILL #20
+        .END
+";
+
+/// A program to run and the keys typed while it runs.
+struct Workload {
+    name: &'static str,
+    image: Image,
+    keys: Vec<u8>,
+}
+
+impl Workload {
+    fn game(name: &'static str, keys: Vec<u8>) -> Self {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("images")
+            .join(name)
+            .with_extension("obj");
+
+        Self {
+            name,
+            image: Image::from_file(&path).unwrap(),
+            keys,
+        }
+    }
+
+    fn kernel(name: &'static str, source: &str) -> Self {
+        Self {
+            name,
+            image: assemble(source).unwrap().image,
+            keys: Vec::new(),
+        }
+    }
+
+    fn vm(&self) -> Vm {
+        let mut memory = Memory::default();
+        memory.set_console(Box::new(Scripted::new(self.keys.clone(), io::sink())));
+        memory.load(&self.image);
+
+        Vm::new(Registers::default(), memory)
+    }
+
+    /// Runs until the program halts or asks for more keys than the script has, returning
+    /// the number of executed instructions.
+    fn run(&self) -> u64 {
+        let mut vm = self.vm();
+        if let Err(e) = vm.run() {
+            assert!(vm.memory().input_ended(), "{}: {}", self.name, e);
+        }
+
+        vm.instructions()
+    }
+}
+
+/// `moves` repeated until there are `count` keys.
+fn keys(start: &[u8], moves: &[u8], count: usize) -> Vec<u8> {
+    start
+        .iter()
+        .chain(moves.iter().cycle().take(count))
+        .copied()
+        .collect()
+}
+
+fn interpreter(c: &mut Criterion) {
+    let workloads = [
+        // No ANSI escapes, then slide the tiles around.
+        Workload::game("2048", keys(b"n", b"wasdsdwa", 400)),
+        // Any key to start, then wander the dungeon.
+        Workload::game("rogue", keys(b" ", b"ddddssssaaaawwwwdsdsawaw", 400)),
+        Workload::kernel("multiply", MULTIPLY),
+        Workload::kernel("sieve", SIEVE),
+        Workload::kernel("sort", SORT),
+        Workload::kernel("fibonacci", FIBONACCI),
+    ];
+
+    let mut group = c.benchmark_group("interpreter");
+    group.sample_size(20);
+    for workload in &workloads {
+        group.throughput(Throughput::Elements(workload.run()));
+        group.bench_function(workload.name, |b| b.iter(|| workload.run()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
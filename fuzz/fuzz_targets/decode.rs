@@ -0,0 +1,28 @@
+//! Reads arbitrary bytes as an image in every format, then inspects and disassembles
+//! whatever loads.
+#![no_main]
+
+use std::io;
+
+use lc3_vm::{
+    disasm,
+    formats::{self, Format},
+    objinfo,
+    symbols::SymbolTable,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    let symbols = SymbolTable::parse(&String::from_utf8_lossy(bytes)).ok();
+
+    Format::detect(bytes);
+    for format in [Format::Obj, Format::Hex, Format::Bin, Format::IntelHex] {
+        let Ok(images) = formats::read(bytes, format) else {
+            continue;
+        };
+        for image in &images {
+            objinfo::inspect(image);
+            disasm::write_listing(&mut io::sink(), image, symbols.as_ref()).unwrap();
+        }
+    }
+});
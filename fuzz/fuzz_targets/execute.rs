@@ -0,0 +1,31 @@
+//! Runs arbitrary bytes as an `.obj` image on both engines, with scripted keys and
+//! discarded output, until it halts, fails or uses up its instruction budget.
+#![no_main]
+
+use std::io;
+
+use lc3_vm::{
+    console::Scripted, engine::Engine, image::Image, loader, memory::Memory, registers::Registers,
+    vm::Vm,
+};
+use libfuzzer_sys::fuzz_target;
+
+const INSTRUCTION_LIMIT: u64 = 10_000;
+const KEYS: &[u8] = b"wasd\nqy 1";
+
+fuzz_target!(|bytes: &[u8]| {
+    let Ok(image) = Image::from_bytes(bytes) else {
+        return;
+    };
+
+    for engine in [Engine::Interpreter, Engine::Blocks] {
+        let mut memory = Memory::default();
+        loader::load(&mut memory, std::slice::from_ref(&image));
+        memory.set_console(Box::new(Scripted::new(KEYS, io::sink())));
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_engine(engine);
+        vm.set_instruction_limit(INSTRUCTION_LIMIT);
+        let _ = vm.run();
+    }
+});
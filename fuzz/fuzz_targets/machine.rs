@@ -0,0 +1,63 @@
+//! Runs from an arbitrary machine state: memory contents, registers (condition codes
+//! included), PSR, keys and breakpoints, within an instruction budget.
+#![no_main]
+
+use std::io;
+
+use arbitrary::Arbitrary;
+use lc3_vm::{
+    console::Scripted,
+    engine::Engine,
+    memory::Memory,
+    registers::{Register, Registers},
+    vm::{Stop, Vm},
+};
+use libfuzzer_sys::fuzz_target;
+
+const INSTRUCTION_LIMIT: u64 = 10_000;
+
+#[derive(Debug, Arbitrary)]
+struct Machine {
+    /// Runs of words and where to store them.
+    memory: Vec<(u16, Vec<u16>)>,
+    registers: [u16; 8],
+    pc: u16,
+    cond: u16,
+    psr: u16,
+    keys: Vec<u8>,
+    breakpoints: Vec<u16>,
+    blocks: bool,
+}
+
+fuzz_target!(|machine: Machine| {
+    let mut memory = Memory::default();
+    for (addr, words) in &machine.memory {
+        for (offset, &word) in words.iter().enumerate() {
+            memory.write(addr.wrapping_add(offset as u16), word);
+        }
+    }
+    memory.set_console(Box::new(Scripted::new(machine.keys, io::sink())));
+
+    let mut vm = Vm::new(Registers::default(), memory);
+    for (n, &value) in machine.registers.iter().enumerate() {
+        vm.set_register(Register::from_bits(n as u16), value);
+    }
+    vm.set_pc(machine.pc);
+    let _ = vm.set_psr(machine.psr);
+    vm.set_register(Register::COND, machine.cond);
+    if machine.blocks {
+        vm.set_engine(Engine::Blocks);
+    }
+    vm.set_instruction_limit(INSTRUCTION_LIMIT);
+    vm.track_calls();
+    for &addr in &machine.breakpoints {
+        vm.add_breakpoint(addr);
+    }
+
+    // Run through breakpoints until the VM halts, fails or runs out of instructions.
+    while let Ok(stop) = vm.resume() {
+        if stop == Stop::Halted {
+            break;
+        }
+    }
+});
@@ -0,0 +1,715 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    image::Image,
+    instructions::{
+        Add, And, Br, Instruction, Jmp, Jsr, Ld, Ldi, Ldr, Lea, Not, St, Sti, Str, Trap,
+    },
+    linker::{Module, Relocation, RelocationKind},
+    registers::Register,
+    symbols::SymbolTable,
+    traps::TrapCode,
+    utils::parse_number,
+};
+
+/// The result of assembling a source file: the object image and its labels.
+#[derive(Debug)]
+pub struct Assembly {
+    pub image: Image,
+    pub symbols: SymbolTable,
+    /// The source line of each instruction, by address. Directives are not included.
+    pub lines: BTreeMap<u16, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Register(Register),
+    Number(i32),
+    Label(String),
+    Str(String),
+}
+
+/// Everything the first pass learns about a source file.
+struct Program {
+    origin: u16,
+    statements: Vec<Statement>,
+    symbols: SymbolTable,
+    externals: Vec<String>,
+    globals: Vec<String>,
+}
+
+/// What encoding a statement needs besides the statement itself.
+struct Context<'a> {
+    symbols: &'a SymbolTable,
+    externals: &'a [String],
+    relocatable: bool,
+    relocations: Vec<Relocation>,
+}
+
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    addr: u16,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+/// Assembles LC-3 assembly source into an image starting at its `.ORIG` address.
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    let program = first_pass(source, false)?;
+    let (words, _) = second_pass(&program, false)?;
+
+    let lines = program
+        .statements
+        .iter()
+        .filter(|statement| !statement.mnemonic.starts_with('.'))
+        .map(|statement| (statement.addr, statement.line))
+        .collect();
+
+    Ok(Assembly {
+        image: Image {
+            origin: program.origin,
+            words,
+        },
+        symbols: program.symbols,
+        lines,
+    })
+}
+
+/// Assembles LC-3 assembly into a relocatable module for [`crate::linker::link`].
+///
+/// The module is assembled at offset 0 whatever its `.ORIG` says. `.GLOBAL LABEL` exports a
+/// label to other modules and `.EXTERNAL LABEL` declares one defined elsewhere, which can be
+/// the target of `.FILL` and of PC-relative instructions.
+pub fn assemble_module(source: &str) -> Result<Module, String> {
+    let program = first_pass(source, true)?;
+    let (words, relocations) = second_pass(&program, true)?;
+
+    for global in &program.globals {
+        if program.symbols.address(global).is_none() {
+            return Err(format!("global label '{}' is not defined", global));
+        }
+    }
+
+    Ok(Module {
+        words,
+        symbols: program.symbols,
+        globals: program.globals,
+        externals: program.externals,
+        relocations,
+    })
+}
+
+/// Parses every line, assigns addresses and collects the labels.
+///
+/// Relocatable programs are laid out from address 0 instead of their `.ORIG`.
+fn first_pass(source: &str, relocatable: bool) -> Result<Program, String> {
+    let mut origin = None;
+    let mut addr: u32 = 0;
+    let mut statements = Vec::new();
+    let mut symbols = SymbolTable::default();
+    let mut externals = Vec::new();
+    let mut globals = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let at_line = |e: String| format!("line {}: {}", line, e);
+
+        let mut tokens = tokenize(text).map_err(at_line)?.into_iter().peekable();
+
+        let label = match tokens.peek() {
+            Some(Token::Word(word)) if !is_mnemonic(word) => {
+                let label = word.trim_end_matches(':').to_string();
+                tokens.next();
+                Some(label)
+            }
+            _ => None,
+        };
+
+        let mnemonic = match tokens.next() {
+            Some(Token::Word(word)) if is_mnemonic(&word) => word.to_uppercase(),
+            Some(Token::Word(word)) => return Err(at_line(format!("unknown opcode '{}'", word))),
+            Some(Token::Str(_)) => return Err(at_line("unexpected string".to_string())),
+            None => {
+                if let Some(label) = label {
+                    define_label(&mut symbols, &label, origin, addr).map_err(at_line)?;
+                }
+                continue;
+            }
+        };
+
+        let operands = tokens.map(parse_operand).collect::<Result<Vec<_>, _>>();
+        let operands = operands.map_err(at_line)?;
+
+        match mnemonic.as_str() {
+            ".ORIG" => {
+                if origin.is_some() {
+                    return Err(at_line("duplicate .ORIG".to_string()));
+                }
+                let start = match operands[..] {
+                    [Operand::Number(n)] => fit_unsigned(n, 16).map_err(at_line)?,
+                    _ => return Err(at_line(".ORIG expects an address".to_string())),
+                };
+                origin = Some(start);
+                addr = if relocatable { 0 } else { start as u32 };
+                // A label here names the first address, and one on `.END` the address past
+                // the last word.
+                if let Some(label) = label {
+                    define_label(&mut symbols, &label, origin, addr).map_err(at_line)?;
+                }
+                continue;
+            }
+            ".END" => {
+                if let Some(label) = label {
+                    define_label(&mut symbols, &label, origin, addr).map_err(at_line)?;
+                }
+                break;
+            }
+            ".EXTERNAL" | ".GLOBAL" => {
+                if label.is_some() {
+                    return Err(at_line(format!("{} cannot be labeled", mnemonic)));
+                }
+                if operands.is_empty() {
+                    return Err(at_line(format!("{} expects labels", mnemonic)));
+                }
+                for operand in operands {
+                    let Operand::Label(name) = operand else {
+                        return Err(at_line(format!("{} expects labels", mnemonic)));
+                    };
+                    if mnemonic == ".EXTERNAL" {
+                        externals.push(name);
+                    } else {
+                        globals.push(name);
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if origin.is_none() {
+            return Err(at_line("statement before .ORIG".to_string()));
+        }
+
+        if let Some(label) = label {
+            define_label(&mut symbols, &label, origin, addr).map_err(at_line)?;
+        }
+
+        let size = match (mnemonic.as_str(), &operands[..]) {
+            (".BLKW", [Operand::Number(n)]) if *n > 0 => *n as u32,
+            (".BLKW", _) => return Err(at_line(".BLKW expects a positive count".to_string())),
+            (".STRINGZ", [Operand::Str(s)]) => s.len() as u32 + 1,
+            (".STRINGZ", _) => return Err(at_line(".STRINGZ expects a string".to_string())),
+            _ => 1,
+        };
+
+        statements.push(Statement {
+            line,
+            addr: addr as u16,
+            mnemonic,
+            operands,
+        });
+
+        addr += size;
+        if addr > 0x10000 {
+            return Err(at_line("program does not fit in memory".to_string()));
+        }
+    }
+
+    let origin = origin.ok_or_else(|| "missing .ORIG".to_string())?;
+
+    for external in &externals {
+        if symbols.address(external).is_some() {
+            return Err(format!("label '{}' is both defined and external", external));
+        }
+    }
+
+    Ok(Program {
+        origin,
+        statements,
+        symbols,
+        externals,
+        globals,
+    })
+}
+
+/// Encodes every statement, returning the words and the relocations they need.
+fn second_pass(
+    program: &Program,
+    relocatable: bool,
+) -> Result<(Vec<u16>, Vec<Relocation>), String> {
+    let mut context = Context {
+        symbols: &program.symbols,
+        externals: &program.externals,
+        relocatable,
+        relocations: Vec::new(),
+    };
+
+    let mut words = Vec::new();
+    for statement in &program.statements {
+        encode(statement, &mut context, &mut words)
+            .map_err(|e| format!("line {}: {}", statement.line, e))?;
+    }
+
+    Ok((words, context.relocations))
+}
+
+fn define_label(
+    symbols: &mut SymbolTable,
+    label: &str,
+    origin: Option<u16>,
+    addr: u32,
+) -> Result<(), String> {
+    if origin.is_none() {
+        return Err("label before .ORIG".to_string());
+    }
+    if !is_label(label) {
+        return Err(format!("invalid label '{}'", label));
+    }
+    if symbols.address(label).is_some() {
+        return Err(format!("duplicate label '{}'", label));
+    }
+
+    symbols.insert(label, addr as u16);
+
+    Ok(())
+}
+
+impl Context<'_> {
+    /// Whether `label` is declared `.EXTERNAL`, which only relocatable modules may use.
+    fn is_external(&self, label: &str) -> Result<bool, String> {
+        let external = self.externals.iter().any(|external| external == label);
+        if external && !self.relocatable {
+            return Err(format!(
+                "external label '{}' needs a relocatable module",
+                label
+            ));
+        }
+
+        Ok(external)
+    }
+
+    fn address(&self, label: &str) -> Result<u16, String> {
+        self.symbols
+            .address(label)
+            .ok_or_else(|| format!("undefined label '{}'", label))
+    }
+
+    /// The offset from the instruction at `addr` to `operand`, leaving a relocation when the
+    /// label is external.
+    fn pc_offset(
+        &mut self,
+        addr: u16,
+        operand: &Operand,
+        kind: RelocationKind,
+    ) -> Result<i16, String> {
+        match operand {
+            Operand::Number(n) => to_i16(*n),
+            Operand::Label(label) if self.is_external(label)? => {
+                self.relocations.push(Relocation {
+                    offset: addr,
+                    kind,
+                    symbol: Some(label.clone()),
+                });
+                Ok(0)
+            }
+            Operand::Label(label) => to_i16(self.address(label)? as i32 - (addr as i32 + 1)),
+            _ => Err("expected a label or offset".to_string()),
+        }
+    }
+}
+
+fn encode(
+    statement: &Statement,
+    context: &mut Context,
+    words: &mut Vec<u16>,
+) -> Result<(), String> {
+    let addr = statement.addr;
+    let operands = &statement.operands[..];
+
+    let mut pc_offset = |operand: &Operand, kind: RelocationKind| -> Result<i16, String> {
+        context.pc_offset(addr, operand, kind)
+    };
+
+    let instruction = match (statement.mnemonic.as_str(), operands) {
+        ("ADD", [Operand::Register(dr), Operand::Register(sr1), Operand::Register(sr2)]) => {
+            Instruction::Add(Add::register(*dr, *sr1, *sr2)?)
+        }
+        ("ADD", [Operand::Register(dr), Operand::Register(sr1), Operand::Number(imm5)]) => {
+            Instruction::Add(Add::immediate(*dr, *sr1, to_i16(*imm5)?)?)
+        }
+        ("AND", [Operand::Register(dr), Operand::Register(sr1), Operand::Register(sr2)]) => {
+            Instruction::And(And::register(*dr, *sr1, *sr2)?)
+        }
+        ("AND", [Operand::Register(dr), Operand::Register(sr1), Operand::Number(imm5)]) => {
+            Instruction::And(And::immediate(*dr, *sr1, to_i16(*imm5)?)?)
+        }
+        ("NOT", [Operand::Register(dr), Operand::Register(sr)]) => {
+            Instruction::Not(Not::new(*dr, *sr)?)
+        }
+        (br, [target]) if is_branch(br) => {
+            let flags = &br[2..];
+            let (n, z, p) = if flags.is_empty() {
+                (true, true, true)
+            } else {
+                (
+                    flags.contains('N'),
+                    flags.contains('Z'),
+                    flags.contains('P'),
+                )
+            };
+            Instruction::Br(Br::new(
+                n,
+                z,
+                p,
+                pc_offset(target, RelocationKind::PcOffset9)?,
+            )?)
+        }
+        ("JMP", [Operand::Register(base_r)]) => Instruction::Jmp(Jmp::new(*base_r)?),
+        ("RET", []) => Instruction::Jmp(Jmp::new(Register::R7)?),
+        ("JSR", [target]) => {
+            Instruction::Jsr(Jsr::offset(pc_offset(target, RelocationKind::PcOffset11)?)?)
+        }
+        ("JSRR", [Operand::Register(base_r)]) => Instruction::Jsr(Jsr::register(*base_r)?),
+        ("LD", [Operand::Register(dr), target]) => {
+            Instruction::Ld(Ld::new(*dr, pc_offset(target, RelocationKind::PcOffset9)?)?)
+        }
+        ("LDI", [Operand::Register(dr), target]) => Instruction::Ldi(Ldi::new(
+            *dr,
+            pc_offset(target, RelocationKind::PcOffset9)?,
+        )?),
+        ("LEA", [Operand::Register(dr), target]) => Instruction::Lea(Lea::new(
+            *dr,
+            pc_offset(target, RelocationKind::PcOffset9)?,
+        )?),
+        ("ST", [Operand::Register(sr), target]) => {
+            Instruction::St(St::new(*sr, pc_offset(target, RelocationKind::PcOffset9)?)?)
+        }
+        ("STI", [Operand::Register(sr), target]) => Instruction::Sti(Sti::new(
+            *sr,
+            pc_offset(target, RelocationKind::PcOffset9)?,
+        )?),
+        ("LDR", [Operand::Register(dr), Operand::Register(base_r), Operand::Number(offset6)]) => {
+            Instruction::Ldr(Ldr::new(*dr, *base_r, to_i16(*offset6)?)?)
+        }
+        ("STR", [Operand::Register(sr), Operand::Register(base_r), Operand::Number(offset6)]) => {
+            Instruction::Str(Str::new(*sr, *base_r, to_i16(*offset6)?)?)
+        }
+        ("TRAP", [Operand::Number(n)]) => {
+            let trapvect8 = fit_unsigned(*n, 8)?;
+            match TrapCode::try_from(trapvect8) {
+                Ok(trap_code) => Instruction::Trap(Trap::new(trap_code)),
+                // Vectors without a service routine in this VM still assemble.
+                Err(_) => return push(words, 0xF000 | trapvect8),
+            }
+        }
+        ("GETC", []) => Instruction::Trap(Trap::new(TrapCode::GETC)),
+        ("OUT", []) => Instruction::Trap(Trap::new(TrapCode::OUT)),
+        ("PUTS", []) => Instruction::Trap(Trap::new(TrapCode::PUTS)),
+        ("IN", []) => Instruction::Trap(Trap::new(TrapCode::IN)),
+        ("PUTSP", []) => Instruction::Trap(Trap::new(TrapCode::PUTSP)),
+        ("HALT", []) => Instruction::Trap(Trap::new(TrapCode::HALT)),
+        ("RTI", []) => return push(words, 0x8000),
+        (".FILL", [Operand::Number(n)]) => {
+            if !(-0x8000..=0xFFFF).contains(n) {
+                return Err(format!("{} does not fit in 16 bits", n));
+            }
+            return push(words, *n as u16);
+        }
+        (".FILL", [Operand::Label(label)]) => {
+            let external = context.is_external(label)?;
+            let value = if external { 0 } else { context.address(label)? };
+            if context.relocatable {
+                context.relocations.push(Relocation {
+                    offset: addr,
+                    kind: RelocationKind::Absolute,
+                    symbol: external.then(|| label.clone()),
+                });
+            }
+            return push(words, value);
+        }
+        (".BLKW", [Operand::Number(n)]) => {
+            words.resize(words.len() + *n as usize, 0);
+            return Ok(());
+        }
+        (".STRINGZ", [Operand::Str(s)]) => {
+            words.extend(s.bytes().map(u16::from));
+            return push(words, 0);
+        }
+        (mnemonic, _) => return Err(format!("invalid operands for {}", mnemonic)),
+    };
+
+    push(words, instruction.encode())
+}
+
+fn push(words: &mut Vec<u16>, word: u16) -> Result<(), String> {
+    words.push(word);
+
+    Ok(())
+}
+
+/// Splits a line into words and string literals, dropping the `;` comment.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('0') => s.push('\0'),
+                            Some('e') => s.push('\x1b'),
+                            Some(c @ ('"' | '\\')) => s.push(c),
+                            Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    const MNEMONICS: &[&str] = &[
+        "ADD",
+        "AND",
+        "NOT",
+        "JMP",
+        "RET",
+        "JSR",
+        "JSRR",
+        "LD",
+        "LDI",
+        "LDR",
+        "LEA",
+        "ST",
+        "STI",
+        "STR",
+        "TRAP",
+        "RTI",
+        "GETC",
+        "OUT",
+        "PUTS",
+        "IN",
+        "PUTSP",
+        "HALT",
+        ".ORIG",
+        ".FILL",
+        ".BLKW",
+        ".STRINGZ",
+        ".END",
+        ".EXTERNAL",
+        ".GLOBAL",
+    ];
+    let word = word.to_uppercase();
+
+    MNEMONICS.contains(&word.as_str()) || is_branch(&word)
+}
+
+fn is_branch(word: &str) -> bool {
+    matches!(
+        word,
+        "BR" | "BRN" | "BRZ" | "BRP" | "BRNZ" | "BRNP" | "BRZP" | "BRNZP"
+    )
+}
+
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_number(word).is_none()
+}
+
+fn parse_operand(token: Token) -> Result<Operand, String> {
+    let word = match token {
+        Token::Str(s) => return Ok(Operand::Str(s)),
+        Token::Word(word) => word,
+    };
+
+    if let [b'R' | b'r', n @ b'0'..=b'7'] = word.as_bytes() {
+        return Ok(Operand::Register(Register::try_from((n - b'0') as u16)?));
+    }
+    if let Some(n) = parse_number(&word) {
+        return Ok(Operand::Number(n));
+    }
+    if is_label(&word) {
+        return Ok(Operand::Label(word));
+    }
+
+    Err(format!("invalid operand '{}'", word))
+}
+
+fn to_i16(value: i32) -> Result<i16, String> {
+    i16::try_from(value).map_err(|_| format!("{} does not fit in 16 bits", value))
+}
+
+fn fit_unsigned(value: i32, bits: u8) -> Result<u16, String> {
+    if value < 0 || value >= 1 << bits {
+        return Err(format!("{} does not fit in {} bits", value, bits));
+    }
+
+    Ok(value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_program() {
+        let source = "
+            ; prints a greeting
+                    .ORIG x3000
+            MAIN    LEA R0, MSG
+                    PUTS
+                    ADD R1, R1, #-3
+                    AND R2, R2, R3
+                    BRnz MAIN
+                    HALT
+            MSG     .STRINGZ \"Hi\\n\"
+                    .END
+        ";
+
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.image.origin, 0x3000);
+        assert_eq!(
+            assembly.image.words,
+            vec![
+                0b1110_000_000000101,
+                0xF022,
+                0b0001_001_001_1_11101,
+                0b0101_010_010_0_00_011,
+                0b0000_110_111111011,
+                0xF025,
+                'H' as u16,
+                'i' as u16,
+                '\n' as u16,
+                0,
+            ]
+        );
+        assert_eq!(assembly.symbols.address("MAIN"), Some(0x3000));
+        assert_eq!(assembly.symbols.address("MSG"), Some(0x3006));
+        assert_eq!(assembly.lines.get(&0x3000), Some(&4));
+        assert_eq!(assembly.lines.get(&0x3005), Some(&9));
+        assert_eq!(assembly.lines.get(&0x3006), None);
+    }
+
+    #[test]
+    fn test_assemble_directives() {
+        let source = "
+            .ORIG x4000
+            PTR .FILL DATA
+            .FILL #-1
+            DATA .BLKW 2
+            .FILL x1234
+            .END
+        ";
+
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.image.origin, 0x4000);
+        assert_eq!(assembly.image.words, vec![0x4002, 0xFFFF, 0, 0, 0x1234]);
+    }
+
+    #[test]
+    fn test_assemble_labeled_orig_and_end() {
+        let source = "
+            START .ORIG x3000
+            LEA R0, START
+            LD R1, SIZE
+            HALT
+            SIZE .FILL END
+            END .END
+        ";
+
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.symbols.address("START"), Some(0x3000));
+        assert_eq!(assembly.symbols.address("END"), Some(0x3004));
+        assert_eq!(assembly.image.words[0], 0b1110_000_111111111);
+        assert_eq!(assembly.image.words[3], 0x3004);
+    }
+
+    #[test]
+    fn test_assemble_control_flow() {
+        let source = "
+            .ORIG x3000
+            LOOP JSR SUB
+            JSRR R2
+            JMP R3
+            BR LOOP
+            SUB RET
+            TRAP x23
+            .END
+        ";
+
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.image.words,
+            vec![0x4803, 0x4080, 0xC0C0, 0x0FFC, 0xC1C0, 0xF023]
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert!(assemble("ADD R0, R0, #1")
+            .unwrap_err()
+            .contains("before .ORIG"));
+        assert!(assemble(".ORIG x3000\nBR NOWHERE\n.END")
+            .unwrap_err()
+            .contains("undefined label 'NOWHERE'"));
+        assert!(assemble(".ORIG x3000\nADD R0, R0, #16\n.END")
+            .unwrap_err()
+            .starts_with("line 2:"));
+        assert!(assemble(".ORIG x3000\nA ADD R0, R0, R0\nA HALT\n.END")
+            .unwrap_err()
+            .contains("duplicate label"));
+        assert!(assemble(".ORIG x3000\nFOO R0\n.END")
+            .unwrap_err()
+            .contains("unknown opcode"));
+        assert!(assemble(".EXTERNAL FOO\n.ORIG x3000\nJSR FOO\n.END")
+            .unwrap_err()
+            .contains("needs a relocatable module"));
+        assert!(assemble_module(".GLOBAL FOO\n.ORIG x3000\nRET\n.END")
+            .unwrap_err()
+            .contains("not defined"));
+    }
+}
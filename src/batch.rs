@@ -0,0 +1,427 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::spec::{self, CaseResult, Outcome, Spec};
+
+/// How often the watchdog looks for runs past their deadline.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The results of one program graded against a spec.
+#[derive(Debug)]
+pub struct Submission {
+    pub path: PathBuf,
+    pub results: Vec<CaseResult>,
+    pub duration: Duration,
+}
+
+impl Submission {
+    pub fn passed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Passed))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Error(_)))
+    }
+
+    fn count(&self, matches: impl Fn(&Outcome) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|result| matches(&result.outcome))
+            .count()
+    }
+}
+
+/// Runs every case of `spec` on each of the `submissions`, on `jobs` threads, giving each
+/// run up to `timeout` of wall-clock time. Each submission is loaded before the images the
+/// spec lists, which can add fixtures such as data. Programs read their keys from the spec
+/// and print into memory, so the host terminal is left alone. The results are in the order
+/// of `submissions`.
+pub fn run(
+    spec: &Spec,
+    submissions: &[PathBuf],
+    jobs: usize,
+    timeout: Option<Duration>,
+) -> Vec<Submission> {
+    let jobs = jobs.clamp(1, submissions.len().max(1));
+    let watchdog = Watchdog::new(jobs);
+    let next = AtomicUsize::new(0);
+
+    let mut graded: Vec<(usize, Submission)> = thread::scope(|scope| {
+        if timeout.is_some() {
+            scope.spawn(|| watchdog.watch());
+        }
+
+        let workers: Vec<_> = (0..jobs)
+            .map(|worker| {
+                let (watchdog, next) = (&watchdog, &next);
+                scope.spawn(move || {
+                    let mut graded = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(path) = submissions.get(index) else {
+                            break;
+                        };
+                        let timer = timeout.map(|timeout| (watchdog, worker, timeout));
+                        graded.push((index, grade(spec, path, timer)));
+                    }
+                    graded
+                })
+            })
+            .collect();
+
+        let graded = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+        watchdog.done.store(true, Ordering::SeqCst);
+        graded
+    });
+
+    graded.sort_by_key(|&(index, _)| index);
+    graded
+        .into_iter()
+        .map(|(_, submission)| submission)
+        .collect()
+}
+
+/// Runs the cases on one submission, timing each run with `timer`: the watchdog, the
+/// worker's slot in it and how long a run may take.
+fn grade(spec: &Spec, path: &Path, timer: Option<(&Watchdog, usize, Duration)>) -> Submission {
+    let start = Instant::now();
+    let submission = spec::load_images(Path::new(""), slice::from_ref(&path.to_path_buf()));
+
+    let results = spec
+        .cases
+        .iter()
+        .map(|case| {
+            let (mut images, mut symbols) = match &submission {
+                Ok(submission) => submission.clone(),
+                Err(e) => return CaseResult::error(case, e.clone()),
+            };
+            match spec.load_images(case) {
+                Ok((fixtures, fixture_symbols)) => {
+                    images.extend(fixtures);
+                    symbols.merge(&fixture_symbols);
+                }
+                Err(e) => return CaseResult::error(case, e),
+            }
+
+            let mut limits = spec.limits(case);
+            limits.timeout =
+                timer.map(|(watchdog, worker, timeout)| watchdog.start(worker, timeout));
+            // A bug in the VM fails this case rather than the whole batch.
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| case.run(&images, &symbols, limits)))
+                    .unwrap_or_else(|_| CaseResult::error(case, String::from("the VM panicked")));
+            if let Some((watchdog, worker, _)) = timer {
+                watchdog.stop(worker);
+            }
+
+            result
+        })
+        .collect();
+
+    Submission {
+        path: path.to_path_buf(),
+        results,
+        duration: start.elapsed(),
+    }
+}
+
+/// Gives each worker an interrupt flag for [`Vm::set_interrupt`](crate::vm::Vm::set_interrupt)
+/// and raises it once the run the worker is timing passes its deadline.
+struct Watchdog {
+    flags: Vec<Arc<AtomicBool>>,
+    deadlines: Mutex<Vec<Option<Instant>>>,
+    done: AtomicBool,
+}
+
+impl Watchdog {
+    fn new(workers: usize) -> Self {
+        Self {
+            flags: (0..workers)
+                .map(|_| Arc::new(AtomicBool::new(false)))
+                .collect(),
+            deadlines: Mutex::new(vec![None; workers]),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts timing a run on `worker`, returning the flag to stop it with.
+    fn start(&self, worker: usize, timeout: Duration) -> Arc<AtomicBool> {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        self.flags[worker].store(false, Ordering::SeqCst);
+        deadlines[worker] = Some(Instant::now() + timeout);
+
+        Arc::clone(&self.flags[worker])
+    }
+
+    fn stop(&self, worker: usize) {
+        self.deadlines.lock().unwrap()[worker] = None;
+    }
+
+    fn watch(&self) {
+        while !self.done.load(Ordering::SeqCst) {
+            thread::sleep(WATCHDOG_INTERVAL);
+
+            let now = Instant::now();
+            let mut deadlines = self.deadlines.lock().unwrap();
+            for (deadline, flag) in deadlines.iter_mut().zip(&self.flags) {
+                if deadline.is_some_and(|deadline| deadline <= now) {
+                    flag.store(true, Ordering::SeqCst);
+                    *deadline = None;
+                }
+            }
+        }
+    }
+}
+
+/// A table of how many cases each submission passed, failed and errored on.
+pub fn write_summary(out: &mut impl Write, submissions: &[Submission]) -> io::Result<()> {
+    let width = submissions
+        .iter()
+        .map(|submission| submission.path.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("submission".len());
+
+    writeln!(
+        out,
+        "{:<width$}  {:>6}  {:>6}  {:>6}  {:>8}",
+        "submission", "passed", "failed", "errors", "seconds"
+    )?;
+    for submission in submissions {
+        writeln!(
+            out,
+            "{:<width$}  {:>6}  {:>6}  {:>6}  {:>8.3}",
+            submission.path.display(),
+            format!("{}/{}", submission.passed(), submission.results.len()),
+            submission.failed(),
+            submission.errors(),
+            submission.duration.as_secs_f64()
+        )?;
+    }
+
+    let all_passed = submissions
+        .iter()
+        .filter(|submission| submission.passed() == submission.results.len())
+        .count();
+    writeln!(
+        out,
+        "{} of {} submissions passed every case",
+        all_passed,
+        submissions.len()
+    )
+}
+
+/// Writes `<name>.json` and `<name>.xml` results into `dir` for each submission, named by
+/// [`result_names`].
+pub fn write_results(dir: &Path, submissions: &[Submission]) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    for (submission, name) in submissions.iter().zip(result_names(submissions)) {
+        let json = dir.join(format!("{}.json", name));
+        fs::write(&json, format!("{:#}\n", spec::to_json(&submission.results)))
+            .map_err(|e| format!("{}: {}", json.display(), e))?;
+
+        let junit = dir.join(format!("{}.xml", name));
+        let file = File::create(&junit).map_err(|e| format!("{}: {}", junit.display(), e))?;
+        spec::write_junit(&mut BufWriter::new(file), &name, &submission.results)
+            .map_err(|e| format!("{}: {}", junit.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// A distinct file name, without extension, for each submission's results: its path from
+/// the directory all submissions share, without the extension and with `-` between
+/// directories, so that `a/hw.obj` and `b/hw.obj` become `a-hw` and `b-hw`. A name that
+/// still repeats, such as for a file given twice, gets the submission's number appended.
+pub fn result_names(submissions: &[Submission]) -> Vec<String> {
+    let mut dirs = submissions
+        .iter()
+        .map(|submission| submission.path.parent().unwrap_or(Path::new("")));
+    let first = dirs.next().unwrap_or(Path::new(""));
+    let common = dirs.fold(first.to_path_buf(), |common, dir| {
+        common
+            .components()
+            .zip(dir.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect()
+    });
+
+    let names: Vec<String> = submissions
+        .iter()
+        .map(|submission| {
+            let path = submission
+                .path
+                .strip_prefix(&common)
+                .unwrap_or(&submission.path);
+            let mut parts: Vec<String> = path
+                .parent()
+                .into_iter()
+                .flat_map(|dir| dir.components())
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .filter(|part| part != "/")
+                .collect();
+            parts.push(
+                path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            parts.join("-")
+        })
+        .collect();
+
+    names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            if names.iter().filter(|other| *other == name).count() > 1 {
+                format!("{}-{}", name, index + 1)
+            } else {
+                name.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::assembler::assemble;
+
+    const SPEC: &str = r#"
+        [[cases]]
+        name = "sets R0"
+        expect = { registers = { R0 = 5 } }
+        "#;
+
+    /// Assembles each program into an object file in a fresh temporary directory.
+    fn write_submissions(name: &str, sources: &[&str]) -> Vec<PathBuf> {
+        let dir = env::temp_dir().join(format!("lc3-batch-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| {
+                let path = dir.join(format!("{}.obj", index));
+                let mut bytes = Vec::new();
+                assemble(source).unwrap().image.write(&mut bytes).unwrap();
+                fs::write(&path, bytes).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch() {
+        let submissions = write_submissions(
+            "grades",
+            &[
+                ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #5\nHALT\n.END",
+                ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #4\nHALT\n.END",
+                ".ORIG x3000\nLOOP BRnzp LOOP\n.END",
+                ".ORIG x3000\n.FILL xD000\n.END",
+            ],
+        );
+        let mut paths = submissions.clone();
+        paths.push(PathBuf::from("missing.obj"));
+        let spec = Spec::from_toml(SPEC).unwrap();
+
+        let graded = run(&spec, &paths, 3, Some(Duration::from_millis(50)));
+
+        let outcomes: Vec<&Outcome> = graded
+            .iter()
+            .map(|submission| &submission.results[0].outcome)
+            .collect();
+        assert_eq!(outcomes[0], &Outcome::Passed);
+        assert_eq!(
+            outcomes[1],
+            &Outcome::Failed(vec![String::from("R0: expected x0005, got x0004")])
+        );
+        assert_eq!(
+            outcomes[2],
+            &Outcome::Error(String::from("timed out at x3000"))
+        );
+        assert_eq!(
+            outcomes[3],
+            &Outcome::Error(String::from("unsupported opcode RES at x3000 (xD000)"))
+        );
+        assert!(matches!(outcomes[4], Outcome::Error(e) if e.starts_with("missing.obj")));
+
+        let mut summary = Vec::new();
+        write_summary(&mut summary, &graded).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary.starts_with("submission "));
+        assert!(summary.contains("missing.obj"));
+        assert!(summary.ends_with("1 of 5 submissions passed every case\n"));
+
+        fs::remove_dir_all(submissions[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_results_of_same_named_submissions() {
+        let spec = Spec::from_toml(SPEC).unwrap();
+        let submission = |path: &str| Submission {
+            path: PathBuf::from(path),
+            results: Vec::new(),
+            duration: Duration::ZERO,
+        };
+        let submissions = [
+            submission("class/a/hw.obj"),
+            submission("class/b/hw.obj"),
+            submission("class/b/late/hw.obj"),
+            submission("class/a/hw.obj"),
+        ];
+
+        assert_eq!(
+            result_names(&submissions),
+            ["a-hw-1", "b-hw", "b-late-hw", "a-hw-4"]
+        );
+        assert_eq!(result_names(&submissions[1..2]), ["hw"]);
+
+        let paths = write_submissions("results", &[".ORIG x3000\nHALT\n.END"]);
+        let dir = paths[0].parent().unwrap();
+        for name in ["a", "b"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            fs::copy(&paths[0], dir.join(name).join("hw.obj")).unwrap();
+        }
+        let graded = run(
+            &spec,
+            &[dir.join("a").join("hw.obj"), dir.join("b").join("hw.obj")],
+            2,
+            None,
+        );
+
+        let results = dir.join("results");
+        write_results(&results, &graded).unwrap();
+
+        for name in ["a-hw", "b-hw"] {
+            assert!(results.join(format!("{}.json", name)).is_file());
+            assert!(results.join(format!("{}.xml", name)).is_file());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
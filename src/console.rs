@@ -0,0 +1,64 @@
+#[cfg(feature = "std")]
+use std::{collections::VecDeque, io::Write};
+
+/// Where the keyboard and display device registers and the I/O traps read keys from and
+/// write characters to.
+pub trait Console {
+    /// Waits for the next key. A key of 0 means none was pressed, and `None` that input has
+    /// ended for good.
+    fn read_key(&mut self) -> Option<u8>;
+
+    fn write(&mut self, text: &str);
+
+    fn flush(&mut self);
+}
+
+/// No keyboard or display: there is never a key to read and output is dropped.
+#[derive(Debug, Default)]
+pub struct Disconnected;
+
+impl Console for Disconnected {
+    fn read_key(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _text: &str) {}
+
+    fn flush(&mut self) {}
+}
+
+/// Types a fixed sequence of keys and sends output to `W`, to run programs without a
+/// terminal.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Scripted<W> {
+    keys: VecDeque<u8>,
+    output: W,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Scripted<W> {
+    pub fn new(keys: impl Into<Vec<u8>>, output: W) -> Self {
+        Self {
+            keys: keys.into().into(),
+            output,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Console for Scripted<W> {
+    fn read_key(&mut self) -> Option<u8> {
+        self.keys.pop_front()
+    }
+
+    fn write(&mut self, text: &str) {
+        self.output
+            .write_all(text.as_bytes())
+            .expect("failed to write output");
+    }
+
+    fn flush(&mut self) {
+        self.output.flush().expect("failed to flush");
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+use crate::{
+    assembler::Assembly,
+    image::Image,
+    instructions::Instruction,
+    memory::MEMORY_SIZE,
+    objinfo,
+    registers::Registers,
+    symbols::{format_address, SymbolTable},
+};
+
+/// How often a conditional branch went each way.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Executed addresses and branch outcomes gathered while the VM runs.
+#[derive(Debug)]
+pub struct Coverage {
+    executions: Vec<u64>,
+    branches: BTreeMap<u16, BranchCoverage>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            executions: vec![0; MEMORY_SIZE],
+            branches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Coverage {
+    /// Counts `instruction`, fetched from `pc`, before it executes with `registers`.
+    pub fn record(&mut self, pc: u16, instruction: &Instruction, registers: &Registers) {
+        self.executions[pc as usize] += 1;
+
+        if let Instruction::Br(br) = instruction {
+            if !br.is_unconditional() {
+                let branch = self.branches.entry(pc).or_default();
+                if br.is_taken(registers) {
+                    branch.taken += 1;
+                } else {
+                    branch.not_taken += 1;
+                }
+            }
+        }
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions[addr as usize]
+    }
+
+    /// The outcomes of the conditional branch at `addr`, if it ever executed.
+    pub fn branch(&self, addr: u16) -> Option<BranchCoverage> {
+        self.branches.get(&addr).copied()
+    }
+}
+
+/// The lines of one file that a loaded image came from, to report coverage against.
+#[derive(Debug)]
+pub struct LineMap {
+    file: String,
+    text: Vec<String>,
+    /// The line of each instruction, by address.
+    lines: BTreeMap<u16, usize>,
+    /// Addresses of the conditional branches.
+    branches: BTreeSet<u16>,
+    /// Labels of instructions, reported as functions.
+    functions: Vec<(String, u16)>,
+}
+
+/// Lines and branches of a [`LineMap`] that were covered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub lines: usize,
+    pub lines_hit: usize,
+    pub branches: usize,
+    pub branches_hit: usize,
+}
+
+impl LineMap {
+    /// Maps the instructions of `assembly` back to the lines of `source`, read from `file`.
+    pub fn from_assembly(file: &str, source: &str, assembly: &Assembly) -> Self {
+        Self::new(
+            file,
+            source.lines().map(String::from).collect(),
+            assembly.lines.clone(),
+            &assembly.image,
+            Some(&assembly.symbols),
+        )
+    }
+
+    /// Without the source, the lines are a disassembly listing of `image`, one per word, and
+    /// only the words reachable from its origin count as instructions.
+    pub fn from_image(file: &str, image: &Image, symbols: Option<&SymbolTable>) -> Self {
+        let text = image
+            .words
+            .iter()
+            .enumerate()
+            .map(|(index, &bits)| {
+                let addr = image.address(index);
+                let assembly = match Instruction::try_from(bits) {
+                    Ok(instruction) => instruction.at(addr, symbols).to_string(),
+                    Err(_) => format!(".FILL x{:04X}", bits),
+                };
+                format!(
+                    "{:<12} x{:04X}  {}",
+                    format_address(symbols, addr),
+                    bits,
+                    assembly
+                )
+            })
+            .collect();
+
+        let lines = objinfo::reachable(image)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, reachable)| reachable)
+            .map(|(index, _)| (image.address(index), index + 1))
+            .collect();
+
+        Self::new(file, text, lines, image, symbols)
+    }
+
+    fn new(
+        file: &str,
+        text: Vec<String>,
+        lines: BTreeMap<u16, usize>,
+        image: &Image,
+        symbols: Option<&SymbolTable>,
+    ) -> Self {
+        let branches = lines
+            .keys()
+            .copied()
+            .filter(|&addr| {
+                let bits = image.words[addr.wrapping_sub(image.origin) as usize];
+                matches!(Instruction::try_from(bits), Ok(Instruction::Br(br)) if !br.is_unconditional())
+            })
+            .collect();
+
+        let mut functions: Vec<(String, u16)> = symbols
+            .into_iter()
+            .flat_map(SymbolTable::iter)
+            .filter(|(_, addr)| lines.contains_key(addr))
+            .map(|(label, addr)| (label.to_string(), addr))
+            .collect();
+        functions.sort_by_key(|&(_, addr)| addr);
+
+        Self {
+            file: file.to_string(),
+            text,
+            lines,
+            branches,
+            functions,
+        }
+    }
+
+    pub fn summary(&self, coverage: &Coverage) -> Summary {
+        let branch_outcomes = |addr: u16| {
+            let branch = coverage.branch(addr).unwrap_or_default();
+            (branch.taken > 0) as usize + (branch.not_taken > 0) as usize
+        };
+
+        Summary {
+            lines: self.lines.len(),
+            lines_hit: self
+                .lines
+                .keys()
+                .filter(|&&addr| coverage.executions(addr) > 0)
+                .count(),
+            branches: 2 * self.branches.len(),
+            branches_hit: self
+                .branches
+                .iter()
+                .map(|&addr| branch_outcomes(addr))
+                .sum(),
+        }
+    }
+}
+
+/// Writes one lcov record per map: line hits, branch outcomes and labels as functions.
+pub fn write_lcov(out: &mut impl Write, coverage: &Coverage, maps: &[LineMap]) -> io::Result<()> {
+    for map in maps {
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{}", map.file)?;
+
+        for (label, addr) in &map.functions {
+            writeln!(out, "FN:{},{}", map.lines[addr], label)?;
+        }
+        for (label, addr) in &map.functions {
+            writeln!(out, "FNDA:{},{}", coverage.executions(*addr), label)?;
+        }
+        writeln!(out, "FNF:{}", map.functions.len())?;
+        let functions_hit = map
+            .functions
+            .iter()
+            .filter(|(_, addr)| coverage.executions(*addr) > 0)
+            .count();
+        writeln!(out, "FNH:{}", functions_hit)?;
+
+        for &addr in &map.branches {
+            let line = map.lines[&addr];
+            let (taken, not_taken) = match coverage.branch(addr) {
+                Some(branch) => (branch.taken.to_string(), branch.not_taken.to_string()),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            writeln!(out, "BRDA:{},0,0,{}", line, taken)?;
+            writeln!(out, "BRDA:{},0,1,{}", line, not_taken)?;
+        }
+
+        let summary = map.summary(coverage);
+        writeln!(out, "BRF:{}", summary.branches)?;
+        writeln!(out, "BRH:{}", summary.branches_hit)?;
+
+        let mut lines: Vec<(usize, u16)> = map
+            .lines
+            .iter()
+            .map(|(&addr, &line)| (line, addr))
+            .collect();
+        lines.sort();
+        for (line, addr) in lines {
+            writeln!(out, "DA:{},{}", line, coverage.executions(addr))?;
+        }
+        writeln!(out, "LF:{}", summary.lines)?;
+        writeln!(out, "LH:{}", summary.lines_hit)?;
+
+        writeln!(out, "end_of_record")?;
+    }
+
+    Ok(())
+}
+
+/// Writes a self-contained HTML page with a summary and every line of each map, colored by
+/// whether it executed and, for branches, whether it went both ways.
+pub fn write_html(out: &mut impl Write, coverage: &Coverage, maps: &[LineMap]) -> io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>LC-3 coverage</title>")?;
+    writeln!(out, "<style>")?;
+    writeln!(out, "body {{ font-family: sans-serif; }}")?;
+    writeln!(out, "table {{ border-collapse: collapse; }}")?;
+    writeln!(out, "td, th {{ padding: 0 0.5em; text-align: left; }}")?;
+    writeln!(
+        out,
+        ".source td {{ font-family: monospace; white-space: pre; }}"
+    )?;
+    writeln!(out, ".number {{ text-align: right; color: #666; }}")?;
+    writeln!(out, ".hit {{ background: #dfd; }}")?;
+    writeln!(out, ".miss {{ background: #fdd; }}")?;
+    writeln!(out, ".partial {{ background: #ffc; }}")?;
+    writeln!(out, "</style>")?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>LC-3 coverage</h1>")?;
+
+    writeln!(out, "<table>")?;
+    writeln!(out, "<tr><th>File</th><th>Lines</th><th>Branches</th></tr>")?;
+    for map in maps {
+        let summary = map.summary(coverage);
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&map.file),
+            ratio(summary.lines_hit, summary.lines),
+            ratio(summary.branches_hit, summary.branches)
+        )?;
+    }
+    writeln!(out, "</table>")?;
+
+    for map in maps {
+        let addresses: BTreeMap<usize, u16> = map
+            .lines
+            .iter()
+            .map(|(&addr, &line)| (line, addr))
+            .collect();
+
+        writeln!(out, "<h2>{}</h2>", escape(&map.file))?;
+        writeln!(out, "<table class=\"source\">")?;
+        for (index, text) in map.text.iter().enumerate() {
+            let line = index + 1;
+            let (class, count, branch) = match addresses.get(&line) {
+                None => ("", String::new(), String::new()),
+                Some(&addr) => {
+                    let executions = coverage.executions(addr);
+                    let branch = coverage
+                        .branch(addr)
+                        .filter(|_| map.branches.contains(&addr));
+                    let class = match branch {
+                        _ if executions == 0 => "miss",
+                        Some(branch) if branch.taken == 0 || branch.not_taken == 0 => "partial",
+                        _ => "hit",
+                    };
+                    let branch = match branch {
+                        Some(branch) => {
+                            format!("taken {}, not taken {}", branch.taken, branch.not_taken)
+                        }
+                        None => String::new(),
+                    };
+                    (class, executions.to_string(), branch)
+                }
+            };
+            writeln!(
+                out,
+                "<tr class=\"{}\"><td class=\"number\">{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td></tr>",
+                class,
+                line,
+                count,
+                escape(text),
+                branch
+            )?;
+        }
+        writeln!(out, "</table>")?;
+    }
+
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+
+    Ok(())
+}
+
+fn ratio(hit: usize, total: usize) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+
+    format!(
+        "{}/{} ({:.1}%)",
+        hit,
+        total,
+        100.0 * hit as f64 / total as f64
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::assemble,
+        registers::{CondFlag, Register},
+    };
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+MAIN    ADD R0, R0, #1
+        BRz SKIP
+        ADD R0, R0, #1
+SKIP    HALT
+        .END
+";
+
+    fn run(coverage: &mut Coverage, assembly: &Assembly, steps: &[u16]) {
+        let mut registers = Registers::default();
+        registers.set(Register::COND, CondFlag::POS as u16);
+
+        for &addr in steps {
+            let bits = assembly.image.words[(addr - 0x3000) as usize];
+            coverage.record(addr, &Instruction::try_from(bits).unwrap(), &registers);
+        }
+    }
+
+    #[test]
+    fn test_write_lcov() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut coverage = Coverage::default();
+        run(&mut coverage, &assembly, &[0x3000, 0x3001, 0x3002, 0x3003]);
+        let map = LineMap::from_assembly("prog.asm", SOURCE, &assembly);
+
+        let mut out = Vec::new();
+        write_lcov(&mut out, &coverage, &[map]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "TN:\n\
+             SF:prog.asm\n\
+             FN:2,MAIN\n\
+             FN:5,SKIP\n\
+             FNDA:1,MAIN\n\
+             FNDA:1,SKIP\n\
+             FNF:2\n\
+             FNH:2\n\
+             BRDA:3,0,0,0\n\
+             BRDA:3,0,1,1\n\
+             BRF:2\n\
+             BRH:1\n\
+             DA:2,1\n\
+             DA:3,1\n\
+             DA:4,1\n\
+             DA:5,1\n\
+             LF:4\n\
+             LH:4\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_image_line_map() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut coverage = Coverage::default();
+        run(&mut coverage, &assembly, &[0x3000]);
+        let map = LineMap::from_image("prog.obj", &assembly.image, None);
+
+        assert_eq!(map.text[1], "x3001        x0401  BRz x3003");
+        assert_eq!(
+            map.summary(&coverage),
+            Summary {
+                lines: 4,
+                lines_hit: 1,
+                branches: 2,
+                branches_hit: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_html_escapes_source() {
+        let source = ".ORIG x3000\nHALT ; a < b\n.END\n";
+        let assembly = assemble(source).unwrap();
+        let map = LineMap::from_assembly("prog.asm", source, &assembly);
+
+        let mut out = Vec::new();
+        write_html(&mut out, &Coverage::default(), &[map]).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains("<tr class=\"miss\"><td class=\"number\">2</td><td class=\"number\">0</td><td>HALT ; a &lt; b</td><td></td></tr>"));
+    }
+}
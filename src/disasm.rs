@@ -0,0 +1,76 @@
+use std::io::{self, Write};
+
+use crate::{image::Image, instructions::Instruction, symbols::SymbolTable};
+
+/// Writes an address/hex/assembly listing of `image`, one word per line. Labels from
+/// `symbols` are printed on their own line and used for PC-relative targets.
+pub fn write_listing(
+    out: &mut impl Write,
+    image: &Image,
+    symbols: Option<&SymbolTable>,
+) -> io::Result<()> {
+    for (index, &bits) in image.words.iter().enumerate() {
+        let addr = image.address(index);
+
+        if let Some(label) = symbols.and_then(|symbols| symbols.label(addr)) {
+            writeln!(out, "{}:", label)?;
+        }
+
+        match Instruction::try_from(bits) {
+            Ok(instruction) => writeln!(
+                out,
+                "x{:04X}  x{:04X}  {}",
+                addr,
+                bits,
+                instruction.at(addr, symbols)
+            )?,
+            Err(_) => writeln!(out, "x{:04X}  x{:04X}  .FILL x{:04X}", addr, bits, bits)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing() {
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0b0001_001_001_1_11101, 0b0000_010_111111110, 0xF025, 0x8000],
+        };
+
+        let mut out = Vec::new();
+        write_listing(&mut out, &image, None).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "x3000  x127D  ADD R1, R1, #-3\n\
+             x3001  x05FE  BRz x3000\n\
+             x3002  xF025  TRAP x25\n\
+             x3003  x8000  .FILL x8000\n"
+        );
+    }
+
+    #[test]
+    fn test_listing_with_symbols() {
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0b0001_001_001_1_11101, 0b0000_010_111111110],
+        };
+        let mut symbols = SymbolTable::default();
+        symbols.insert("LOOP", 0x3000);
+
+        let mut out = Vec::new();
+        write_listing(&mut out, &image, Some(&symbols)).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "LOOP:\n\
+             x3000  x127D  ADD R1, R1, #-3\n\
+             x3001  x05FE  BRz LOOP\n"
+        );
+    }
+}
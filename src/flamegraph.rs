@@ -0,0 +1,137 @@
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use crate::{
+    profile::Profile,
+    symbols::{format_address, SymbolTable},
+};
+
+/// Writes the call stacks of `profile` in the folded format read by `flamegraph.pl`,
+/// `inferno-flamegraph` and speedscope: one `OUTER;INNER count` line per stack, counting
+/// executed instructions.
+pub fn write_folded(
+    out: &mut impl Write,
+    profile: &Profile,
+    symbols: Option<&SymbolTable>,
+) -> io::Result<()> {
+    for (stack, instructions) in profile.stacks() {
+        let frames: Vec<String> = stack
+            .into_iter()
+            .map(|entry| format_address(symbols, entry))
+            .collect();
+        writeln!(out, "{} {}", frames.join(";"), instructions)?;
+    }
+
+    Ok(())
+}
+
+/// The calls of `profile` as Chrome trace events, for `chrome://tracing` or Perfetto, with
+/// one span per subroutine call. Each executed instruction counts as a microsecond.
+///
+/// Returns `None` unless the profile was created [`with_spans`](Profile::with_spans).
+pub fn chrome_trace(profile: &Profile, symbols: Option<&SymbolTable>) -> Option<Value> {
+    let events: Vec<Value> = profile
+        .spans()?
+        .into_iter()
+        .map(|span| {
+            json!({
+                "name": format_address(symbols, span.entry),
+                "cat": "subroutine",
+                "ph": "X",
+                "ts": span.start,
+                "dur": span.duration,
+                "pid": 1,
+                "tid": 1,
+                "args": {
+                    "address": format!("x{:04X}", span.entry),
+                    "depth": span.depth,
+                },
+            })
+        })
+        .collect();
+
+    Some(json!({ "traceEvents": events }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{Add, Instruction, Jmp, Jsr};
+    use crate::registers::Register;
+
+    /// Runs MAIN: JSR OUTER; HALT, where OUTER: JSR INNER; RET and INNER: ADD; RET.
+    fn profile() -> Profile {
+        let mut profile = Profile::with_spans();
+        let steps = [
+            (0x3000, Instruction::Jsr(Jsr::offset(0x0F).unwrap()), 0x3010),
+            (0x3010, Instruction::Jsr(Jsr::offset(0x0F).unwrap()), 0x3020),
+            (
+                0x3020,
+                Instruction::Add(Add::immediate(Register::R0, Register::R0, 1).unwrap()),
+                0x3021,
+            ),
+            (
+                0x3021,
+                Instruction::Jmp(Jmp::new(Register::R7).unwrap()),
+                0x3011,
+            ),
+            (
+                0x3011,
+                Instruction::Jmp(Jmp::new(Register::R7).unwrap()),
+                0x3001,
+            ),
+        ];
+        for (pc, instruction, next_pc) in steps {
+            profile.record_execution(pc, instruction.encode());
+            profile.record_control_flow(pc, &instruction, next_pc);
+        }
+        profile.record_execution(0x3001, 0xF025);
+
+        profile
+    }
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::default();
+        symbols.insert("MAIN", 0x3000);
+        symbols.insert("OUTER", 0x3010);
+        symbols.insert("INNER", 0x3020);
+        symbols
+    }
+
+    #[test]
+    fn test_write_folded() {
+        let mut out = Vec::new();
+
+        write_folded(&mut out, &profile(), Some(&symbols())).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "MAIN 2\n\
+             MAIN;OUTER 2\n\
+             MAIN;OUTER;INNER 2\n"
+        );
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let trace = chrome_trace(&profile(), Some(&symbols())).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+
+        let spans: Vec<(&str, u64, u64)> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap(),
+                    event["ts"].as_u64().unwrap(),
+                    event["dur"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![("MAIN", 0, 6), ("OUTER", 1, 4), ("INNER", 2, 2)]
+        );
+        assert!(chrome_trace(&Profile::default(), None).is_none());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::image::Image;
+
+/// The image file formats the VM can load and export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Big-endian words, the first of which is the origin.
+    Obj,
+    /// lc3tools text: one word per line as four hex digits, the first being the origin.
+    Hex,
+    /// lc3tools text: one word per line as sixteen binary digits, the first being the origin.
+    Bin,
+    /// Intel HEX records with byte addresses, two big-endian bytes per LC-3 word.
+    IntelHex,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "obj" => Ok(Self::Obj),
+            "hex" => Ok(Self::Hex),
+            "bin" => Ok(Self::Bin),
+            "ihex" | "intel-hex" => Ok(Self::IntelHex),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Obj => write!(f, "obj"),
+            Self::Hex => write!(f, "hex"),
+            Self::Bin => write!(f, "bin"),
+            Self::IntelHex => write!(f, "ihex"),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format of a file from its contents.
+    pub fn detect(bytes: &[u8]) -> Self {
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            return Self::Obj;
+        };
+        let mut lines = text_lines(text).peekable();
+
+        match lines.peek() {
+            Some(line) if line.starts_with(':') => Self::IntelHex,
+            Some(_) if text_lines(text).all(|line| is_word(line, 2)) => Self::Bin,
+            Some(_) if text_lines(text).all(|line| is_word(line, 16)) => Self::Hex,
+            _ => Self::Obj,
+        }
+    }
+
+    /// The format of an input file: from its extension where that settles it, otherwise
+    /// detected from its contents. A `.hex` file can be lc3tools text or Intel HEX.
+    pub fn of_file(path: &Path, bytes: &[u8]) -> Self {
+        match Self::from_extension(path) {
+            Some(Self::Hex) | None => Self::detect(bytes),
+            Some(format) => format,
+        }
+    }
+
+    /// The format an output file should be written in, judging by its extension.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "obj" => Some(Self::Obj),
+            "hex" => Some(Self::Hex),
+            "bin" => Some(Self::Bin),
+            "ihex" | "ihx" => Some(Self::IntelHex),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the images in `bytes`. Only Intel HEX files can hold more than one.
+pub fn read(bytes: &[u8], format: Format) -> Result<Vec<Image>, String> {
+    match format {
+        Format::Obj => Image::read(bytes).map(|image| vec![image]),
+        Format::Hex => read_text(bytes, 16).map(|image| vec![image]),
+        Format::Bin => read_text(bytes, 2).map(|image| vec![image]),
+        Format::IntelHex => read_intel_hex(bytes),
+    }
+}
+
+/// Reads the images in a file, detecting its format unless one is given.
+pub fn read_file(path: &Path, format: Option<Format>) -> Result<Vec<Image>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let format = format.unwrap_or_else(|| Format::of_file(path, &bytes));
+
+    read(&bytes, format).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Writes `images` in `format`. Only Intel HEX files can hold more than one image.
+pub fn write(out: &mut impl Write, images: &[Image], format: Format) -> Result<(), String> {
+    let image = match (format, images) {
+        (Format::IntelHex, _) => return write_intel_hex(out, images).map_err(|e| e.to_string()),
+        (_, [image]) => image,
+        _ => return Err(format!("the {} format holds exactly one image", format)),
+    };
+
+    let result = match format {
+        Format::Obj => image.write(out),
+        Format::Hex => write_text(out, image, |word| format!("{:04X}", word)),
+        Format::Bin => write_text(out, image, |word| format!("{:016b}", word)),
+        Format::IntelHex => unreachable!(),
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+/// Non-empty lines with `;` comments and surrounding whitespace removed.
+fn text_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.split(';').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+}
+
+fn is_word(line: &str, radix: u32) -> bool {
+    let digits = if radix == 2 { 16 } else { 4 };
+
+    line.len() == digits && line.chars().all(|c| c.is_digit(radix))
+}
+
+fn read_text(bytes: &[u8], radix: u32) -> Result<Image, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+
+    let mut words = text_lines(text).map(|line| {
+        if !is_word(line, radix) {
+            return Err(format!("invalid word '{}'", line));
+        }
+        u16::from_str_radix(line, radix).map_err(|e| e.to_string())
+    });
+
+    let origin = words.next().ok_or("missing origin")??;
+    let words = words.collect::<Result<Vec<_>, _>>()?;
+
+    if origin as usize + words.len() > 0x10000 {
+        return Err("file too large".to_string());
+    }
+
+    Ok(Image { origin, words })
+}
+
+fn write_text(out: &mut impl Write, image: &Image, word: impl Fn(u16) -> String) -> io::Result<()> {
+    writeln!(out, "{}", word(image.origin))?;
+    for &bits in &image.words {
+        writeln!(out, "{}", word(bits))?;
+    }
+
+    Ok(())
+}
+
+fn read_intel_hex(bytes: &[u8]) -> Result<Vec<Image>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+
+    // Wide enough that no record can overflow it; addresses past memory are rejected below.
+    let mut data: BTreeMap<u64, u8> = BTreeMap::new();
+    let mut base: u64 = 0;
+
+    for (index, line) in text_lines(text).enumerate() {
+        let at_line = |e: &str| format!("line {}: {}", index + 1, e);
+
+        let record = line
+            .strip_prefix(':')
+            .filter(|hex| hex.is_ascii() && hex.len() % 2 == 0)
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .ok_or_else(|| at_line("malformed record"))?;
+
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(at_line("bad record length"));
+        }
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(at_line("bad checksum"));
+        }
+
+        let offset = u16::from_be_bytes([record[1], record[2]]) as u64;
+        let payload = &record[4..record.len() - 1];
+
+        match record[3] {
+            0x00 => {
+                for (i, &byte) in payload.iter().enumerate() {
+                    data.insert(base + offset + i as u64, byte);
+                }
+            }
+            0x01 => break,
+            0x02 if payload.len() == 2 => {
+                base = (u16::from_be_bytes([payload[0], payload[1]]) as u64) << 4
+            }
+            0x04 if payload.len() == 2 => {
+                base = (u16::from_be_bytes([payload[0], payload[1]]) as u64) << 16
+            }
+            0x03 | 0x05 => {}
+            _ => return Err(at_line("unsupported record")),
+        }
+    }
+
+    let mut images: Vec<Image> = Vec::new();
+    let mut bytes = data.into_iter().peekable();
+
+    while let Some((addr, high)) = bytes.next() {
+        let low = match bytes.next() {
+            Some((low_addr, low)) if addr % 2 == 0 && low_addr == addr + 1 => low,
+            _ => {
+                return Err(format!(
+                    "byte address {:#X} is not part of a whole word",
+                    addr
+                ))
+            }
+        };
+        let word_addr = u16::try_from(addr / 2)
+            .map_err(|_| format!("byte address {:#X} is outside memory", addr))?;
+        let word = u16::from_be_bytes([high, low]);
+
+        match images.last_mut() {
+            Some(image) if image.end().map(|end| end as u32 + 1) == Some(word_addr as u32) => {
+                image.words.push(word)
+            }
+            _ => images.push(Image {
+                origin: word_addr,
+                words: vec![word],
+            }),
+        }
+    }
+
+    Ok(images)
+}
+
+fn write_intel_hex(out: &mut impl Write, images: &[Image]) -> io::Result<()> {
+    let mut upper = 0;
+
+    for image in images {
+        let bytes: Vec<u8> = image
+            .words
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        let mut addr = image.origin as u32 * 2;
+        let mut rest = &bytes[..];
+
+        while !rest.is_empty() {
+            // Records hold up to 16 bytes and never cross into the next 64K segment.
+            let len = rest.len().min(16).min((0x10000 - (addr & 0xFFFF)) as usize);
+            let (chunk, tail) = rest.split_at(len);
+
+            if addr >> 16 != upper {
+                upper = addr >> 16;
+                write_record(out, 0x04, 0, &(upper as u16).to_be_bytes())?;
+            }
+            write_record(out, 0x00, addr as u16, chunk)?;
+
+            addr += len as u32;
+            rest = tail;
+        }
+    }
+
+    write_record(out, 0x01, 0, &[])
+}
+
+fn write_record(out: &mut impl Write, kind: u8, offset: u16, payload: &[u8]) -> io::Result<()> {
+    let mut bytes = vec![payload.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(payload);
+    bytes.push(
+        bytes
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_add(b))
+            .wrapping_neg(),
+    );
+
+    write!(out, ":")?;
+    for byte in bytes {
+        write!(out, "{:02X}", byte)?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Image {
+        Image {
+            origin: 0x3000,
+            words: vec![0xE002, 0xF022, 0xF025],
+        }
+    }
+
+    #[test]
+    fn test_read_hex() {
+        let text = "3000\nE002\n; comment\nf022\nF025\n";
+
+        assert_eq!(read(text.as_bytes(), Format::Hex), Ok(vec![program()]));
+    }
+
+    #[test]
+    fn test_read_bin() {
+        let text = "0011000000000000\n1110000000000010\n1111000000100010\n1111000000100101\n";
+
+        assert_eq!(read(text.as_bytes(), Format::Bin), Ok(vec![program()]));
+    }
+
+    #[test]
+    fn test_read_intel_hex() {
+        let text = ":06600000E002F022F02591\n:00000001FF\n";
+
+        assert_eq!(read(text.as_bytes(), Format::IntelHex), Ok(vec![program()]));
+    }
+
+    #[test]
+    fn test_intel_hex_bad_checksum_fails() {
+        let text = ":06600000E002F022F02592\n:00000001FF\n";
+
+        assert!(read(text.as_bytes(), Format::IntelHex).is_err());
+    }
+
+    #[test]
+    fn test_intel_hex_malformed_records_fail() {
+        assert!(read(":\u{14}\u{a0}g\u{de}\n".as_bytes(), Format::IntelHex).is_err());
+
+        // The last data byte lands past 4 GiB.
+        let text = ":02000004FFFFFC\n:02FFFF00AABB9B\n:00000001FF\n";
+        assert!(read(text.as_bytes(), Format::IntelHex).is_err());
+    }
+
+    #[test]
+    fn test_obj_with_odd_byte_fails() {
+        assert_eq!(
+            read(&[0x30, 0x00, 0xF0, 0x25, 0x12], Format::Obj),
+            Err("odd trailing byte x12".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extension_decides_format() {
+        // An origin of x3A30 reads as ":0", the start of an Intel HEX record.
+        let bytes = [0x3A, 0x30, 0x10, 0x25];
+
+        assert_eq!(Format::detect(&bytes), Format::IntelHex);
+        assert_eq!(Format::of_file(Path::new("a.obj"), &bytes), Format::Obj);
+        assert_eq!(Format::of_file(Path::new("a"), &bytes), Format::IntelHex);
+
+        let text = b":06600000E002F022F02591\n:00000001FF\n";
+        assert_eq!(Format::of_file(Path::new("a.hex"), text), Format::IntelHex);
+        assert_eq!(
+            Format::of_file(Path::new("a.hex"), b"3000\nF025\n"),
+            Format::Hex
+        );
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let images = vec![
+            program(),
+            Image {
+                origin: 0x7FFE,
+                words: (0..20).collect(),
+            },
+        ];
+
+        for format in [Format::Obj, Format::Hex, Format::Bin, Format::IntelHex] {
+            let expected = if format == Format::IntelHex {
+                images.clone()
+            } else {
+                images[..1].to_vec()
+            };
+
+            let mut out = Vec::new();
+            write(&mut out, &expected, format).unwrap();
+
+            assert_eq!(Format::detect(&out), format);
+            assert_eq!(read(&out, format), Ok(expected), "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_single_image_formats_reject_several_images() {
+        let mut out = Vec::new();
+
+        assert!(write(&mut out, &[program(), program()], Format::Hex).is_err());
+    }
+}
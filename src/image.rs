@@ -0,0 +1,164 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+#[cfg(feature = "std")]
+use byteorder::{BigEndian, WriteBytesExt};
+
+/// A program image: a block of words and the address it is loaded at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Image {
+    /// Parses an image in the big-endian `.obj` format, where the first word is the origin.
+    /// A trailing odd byte is an error rather than being dropped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if let [.., last] = bytes {
+            if bytes.len() % 2 == 1 {
+                return Err(format!("odd trailing byte x{:02X}", last));
+            }
+        }
+
+        let mut words = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        let origin = words
+            .next()
+            .ok_or_else(|| "error reading base address".to_string())?;
+        let words: Vec<u16> = words.collect();
+
+        if origin as usize + words.len() > 0x10000 {
+            return Err("file too large".to_string());
+        }
+
+        Ok(Self { origin, words })
+    }
+
+    /// Reads an image in the `.obj` format, see [`Image::from_bytes`].
+    #[cfg(feature = "std")]
+    pub fn read(mut reader: impl Read) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("error reading image: {}", e))?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::read(std::io::BufReader::new(file))
+    }
+
+    /// Writes the image in the `.obj` format read by [`Image::read`].
+    #[cfg(feature = "std")]
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_u16::<BigEndian>(self.origin)?;
+        for &bits in &self.words {
+            out.write_u16::<BigEndian>(bits)?;
+        }
+
+        Ok(())
+    }
+
+    /// The part of the image between `from` and `to`, both inclusive.
+    pub fn range(&self, from: u16, to: u16) -> Image {
+        let start = from.saturating_sub(self.origin) as usize;
+        let end = (to.saturating_sub(self.origin) as usize + 1).min(self.words.len());
+
+        Image {
+            origin: self.address(start),
+            words: self.words.get(start..end).unwrap_or_default().to_vec(),
+        }
+    }
+
+    /// Address of the last word, or `None` for an empty image.
+    pub fn end(&self) -> Option<u16> {
+        (!self.words.is_empty()).then(|| self.address(self.words.len() - 1))
+    }
+
+    /// Address of the word at `index`, relative to the origin.
+    pub fn address(&self, index: usize) -> u16 {
+        self.origin.wrapping_add(index as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_read_image() {
+        let bytes: &[u8] = &[0x30, 0x00, 0x12, 0x61, 0xF0, 0x25];
+
+        let image = Image::read(bytes).unwrap();
+
+        assert_eq!(image.origin, 0x3000);
+        assert_eq!(image.words, vec![0x1261, 0xF025]);
+        assert_eq!(image.address(1), 0x3001);
+    }
+
+    #[test]
+    fn test_image_from_bytes_rejects_odd_byte() {
+        assert_eq!(
+            Image::from_bytes(&[0x30, 0x00, 0xF0, 0x25, 0x12]),
+            Err("odd trailing byte x12".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_image() {
+        let image = Image {
+            origin: 0x3000,
+            words: vec![0x1261, 0xF025],
+        };
+
+        let mut out = Vec::new();
+        image.write(&mut out).unwrap();
+
+        assert_eq!(out, vec![0x30, 0x00, 0x12, 0x61, 0xF0, 0x25]);
+    }
+
+    #[test]
+    fn test_image_range() {
+        let image = Image {
+            origin: 0x3000,
+            words: vec![1, 2, 3, 4],
+        };
+
+        assert_eq!(image.range(0x3001, 0x3002).words, vec![2, 3]);
+        assert_eq!(image.range(0x3001, 0x3002).origin, 0x3001);
+        assert_eq!(image.range(0x2000, 0x3000).words, vec![1]);
+        assert_eq!(image.range(0x3003, 0xFFFF).words, vec![4]);
+        assert!(image.range(0x4000, 0xFFFF).words.is_empty());
+    }
+
+    #[test]
+    fn test_read_empty_image_fails() {
+        assert!(Image::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_read_image_past_end_of_memory_fails() {
+        let bytes: &[u8] = &[0xFF, 0xFF, 0x00, 0x01, 0x00, 0x02];
+
+        assert!(Image::from_bytes(bytes).is_err());
+    }
+}
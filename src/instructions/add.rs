@@ -1,32 +1,56 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AddImmediate {
     dr: Register,
     sr1: Register,
     imm5: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AddRegister {
     dr: Register,
     sr1: Register,
     sr2: Register,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Add {
     AddImm(AddImmediate),
     AddReg(AddRegister),
 }
 
 impl Add {
+    #[cfg(feature = "alloc")]
+    pub fn immediate(dr: Register, sr1: Register, imm5: i16) -> Result<Self, String> {
+        Ok(Self::AddImm(AddImmediate {
+            dr: general_purpose(dr)?,
+            sr1: general_purpose(sr1)?,
+            imm5: fit_signed(imm5.into(), 5)?,
+        }))
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn register(dr: Register, sr1: Register, sr2: Register) -> Result<Self, String> {
+        Ok(Self::AddReg(AddRegister {
+            dr: general_purpose(dr)?,
+            sr1: general_purpose(sr1)?,
+            sr2: general_purpose(sr2)?,
+        }))
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let dr = Register::try_from((bits >> 9) & 0b111).unwrap();
-        let sr1 = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let dr = Register::from_bits(bits >> 9);
+        let sr1 = Register::from_bits(bits >> 6);
         let imm = (bits >> 5) & 0b1 == 1;
 
         if imm {
@@ -34,12 +58,27 @@ impl Add {
 
             Self::AddImm(AddImmediate { dr, sr1, imm5 })
         } else {
-            let sr2 = Register::try_from(bits & 0b111).unwrap();
+            let sr2 = Register::from_bits(bits);
 
             Self::AddReg(AddRegister { dr, sr1, sr2 })
         }
     }
 
+    pub fn encode(&self) -> u16 {
+        match self {
+            Add::AddImm(args) => {
+                0b0001 << 12
+                    | (args.dr as u16) << 9
+                    | (args.sr1 as u16) << 6
+                    | 1 << 5
+                    | args.imm5 & 0b11111
+            }
+            Add::AddReg(args) => {
+                0b0001 << 12 | (args.dr as u16) << 9 | (args.sr1 as u16) << 6 | args.sr2 as u16
+            }
+        }
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
         match self {
             Add::AddImm(args) => {
@@ -59,8 +98,19 @@ impl Add {
     }
 }
 
+impl fmt::Display for Add {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Add::AddImm(args) => write!(f, "ADD {}, {}, #{}", args.dr, args.sr1, args.imm5 as i16),
+            Add::AddReg(args) => write!(f, "ADD {}, {}, {}", args.dr, args.sr1, args.sr2),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use alloc::string::ToString;
+
     use super::*;
 
     #[test]
@@ -131,4 +181,18 @@ mod tests {
 
         assert_eq!(registers.get(Register::R0), 15);
     }
+
+    #[test]
+    fn test_add_display() {
+        // ADD  R1, R2, #-3
+        assert_eq!(
+            Add::from_bits(0b0001_001_010_1_11101).to_string(),
+            "ADD R1, R2, #-3"
+        );
+        // ADD  R3, R2, R5
+        assert_eq!(
+            Add::from_bits(0b0001_011_010_0_00_101).to_string(),
+            "ADD R3, R2, R5"
+        );
+    }
 }
@@ -1,32 +1,56 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AndImmediate {
     dr: Register,
     sr1: Register,
     imm5: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AndRegister {
     dr: Register,
     sr1: Register,
     sr2: Register,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum And {
     AddImm(AndImmediate),
     AddReg(AndRegister),
 }
 
 impl And {
+    #[cfg(feature = "alloc")]
+    pub fn immediate(dr: Register, sr1: Register, imm5: i16) -> Result<Self, String> {
+        Ok(Self::AddImm(AndImmediate {
+            dr: general_purpose(dr)?,
+            sr1: general_purpose(sr1)?,
+            imm5: fit_signed(imm5.into(), 5)?,
+        }))
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn register(dr: Register, sr1: Register, sr2: Register) -> Result<Self, String> {
+        Ok(Self::AddReg(AndRegister {
+            dr: general_purpose(dr)?,
+            sr1: general_purpose(sr1)?,
+            sr2: general_purpose(sr2)?,
+        }))
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let dr = Register::try_from((bits >> 9) & 0b111).unwrap();
-        let sr1 = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let dr = Register::from_bits(bits >> 9);
+        let sr1 = Register::from_bits(bits >> 6);
         let imm = (bits >> 5) & 0b1 == 1;
 
         if imm {
@@ -34,12 +58,27 @@ impl And {
 
             Self::AddImm(AndImmediate { dr, sr1, imm5 })
         } else {
-            let sr2 = Register::try_from(bits & 0b111).unwrap();
+            let sr2 = Register::from_bits(bits);
 
             Self::AddReg(AndRegister { dr, sr1, sr2 })
         }
     }
 
+    pub fn encode(&self) -> u16 {
+        match self {
+            And::AddImm(args) => {
+                0b0101 << 12
+                    | (args.dr as u16) << 9
+                    | (args.sr1 as u16) << 6
+                    | 1 << 5
+                    | args.imm5 & 0b11111
+            }
+            And::AddReg(args) => {
+                0b0101 << 12 | (args.dr as u16) << 9 | (args.sr1 as u16) << 6 | args.sr2 as u16
+            }
+        }
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
         match self {
             And::AddImm(args) => {
@@ -53,3 +92,12 @@ impl And {
         }
     }
 }
+
+impl fmt::Display for And {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            And::AddImm(args) => write!(f, "AND {}, {}, #{}", args.dr, args.sr1, args.imm5 as i16),
+            And::AddReg(args) => write!(f, "AND {}, {}, {}", args.dr, args.sr1, args.sr2),
+        }
+    }
+}
@@ -1,9 +1,16 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::utils::fit_signed;
 use crate::{
+    instructions::Location,
     registers::{CondFlag, Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Br {
     n: bool,
     z: bool,
@@ -12,6 +19,16 @@ pub struct Br {
 }
 
 impl Br {
+    #[cfg(feature = "alloc")]
+    pub fn new(n: bool, z: bool, p: bool, pc_offset9: i16) -> Result<Self, String> {
+        Ok(Self {
+            n,
+            z,
+            p,
+            pc_offset9: fit_signed(pc_offset9.into(), 9)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
         let n = (bits >> 11) & 0b1 == 1;
         let z = (bits >> 10) & 0b1 == 1;
@@ -27,24 +44,63 @@ impl Br {
         }
     }
 
-    pub fn execute(&self, registers: &mut Registers) {
-        let cond = CondFlag::try_from(registers.get(Register::COND)).unwrap();
-
-        let branch = match cond {
-            CondFlag::POS if self.p => true,
-            CondFlag::ZRO if self.z => true,
-            CondFlag::NEG if self.n => true,
-            _ => false,
-        };
+    pub fn encode(&self) -> u16 {
+        (self.n as u16) << 11
+            | (self.z as u16) << 10
+            | (self.p as u16) << 9
+            | self.pc_offset9 & 0x1ff
+    }
 
-        // If the branch condition is satisfied, or branch unconditional.
-        if branch || !(self.n || self.p || self.z) {
+    pub fn execute(&self, registers: &mut Registers) {
+        if self.is_taken(registers) {
             registers.set(
                 Register::PC,
                 registers.get(Register::PC).wrapping_add(self.pc_offset9),
             );
         }
     }
+
+    /// Whether the branch would be taken with the condition codes in `registers`. With no
+    /// flags set it never is, as on the hardware.
+    pub fn is_taken(&self, registers: &Registers) -> bool {
+        // Tested bit by bit, since COND can be set to anything from outside.
+        let cond = registers.get(Register::COND);
+
+        (self.n && cond & CondFlag::NEG as u16 != 0)
+            || (self.z && cond & CondFlag::ZRO as u16 != 0)
+            || (self.p && cond & CondFlag::POS as u16 != 0)
+    }
+
+    /// Where the branch goes when taken, for a branch at `addr`.
+    pub fn target(&self, addr: u16) -> u16 {
+        addr.wrapping_add(1).wrapping_add(self.pc_offset9)
+    }
+
+    /// Whether the branch is always taken: `BRnzp`, which `BR` assembles to.
+    pub fn is_unconditional(&self) -> bool {
+        self.n && self.z && self.p
+    }
+
+    pub(super) fn fmt_at(&self, f: &mut fmt::Formatter, location: Location) -> fmt::Result {
+        write!(f, "BR")?;
+        if self.n {
+            write!(f, "n")?;
+        }
+        if self.z {
+            write!(f, "z")?;
+        }
+        if self.p {
+            write!(f, "p")?;
+        }
+        write!(f, " ")?;
+        location.write_target(f, self.pc_offset9)
+    }
+}
+
+impl fmt::Display for Br {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, Location::default())
+    }
 }
 
 #[cfg(test)]
@@ -65,8 +121,8 @@ mod tests {
     }
 
     #[test]
-    fn test_br_unconditional() {
-        // BR   0x9
+    fn test_br_without_flags_is_never_taken() {
+        // NOP: BR with no flags set, 0x9
         let bits = 0b0000_000_000001001;
 
         let instruction = Br::from_bits(bits);
@@ -81,7 +137,7 @@ mod tests {
 
         instruction.execute(&mut registers);
 
-        assert_eq!(registers.get(Register::PC), 0x9009);
+        assert_eq!(registers.get(Register::PC), 0x9000);
     }
 
     #[test]
@@ -134,4 +190,20 @@ mod tests {
 
         assert_eq!(registers.get(Register::PC), 0x9009);
     }
+
+    #[test]
+    fn test_br_with_invalid_cond() {
+        // BRnz   0x9
+        let instruction = Br::from_bits(0b0000_110_000001001);
+
+        let mut registers = Registers::default();
+        registers.set(Register::PC, 0x9000);
+        registers.set(Register::COND, 0);
+        instruction.execute(&mut registers);
+        assert_eq!(registers.get(Register::PC), 0x9000);
+
+        registers.set(Register::COND, 0xFFFF);
+        instruction.execute(&mut registers);
+        assert_eq!(registers.get(Register::PC), 0x9009);
+    }
 }
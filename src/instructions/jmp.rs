@@ -1,24 +1,57 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::instructions::general_purpose;
 use crate::registers::{Register, Registers};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Jmp {
     base_r: Register,
 }
 
 impl Jmp {
+    #[cfg(feature = "alloc")]
+    pub fn new(base_r: Register) -> Result<Self, String> {
+        Ok(Self {
+            base_r: general_purpose(base_r)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let base_r = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let base_r = Register::from_bits(bits >> 6);
 
         Self { base_r }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b1100 << 12 | (self.base_r as u16) << 6
+    }
+
+    /// Whether this is `RET`, the return from a subroutine.
+    pub fn is_return(&self) -> bool {
+        self.base_r == Register::R7
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
         registers.set(Register::PC, registers.get(self.base_r));
     }
 }
 
+impl fmt::Display for Jmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.base_r {
+            Register::R7 => write!(f, "RET"),
+            base_r => write!(f, "JMP {}", base_r),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use alloc::string::ToString;
+
     use super::*;
 
     #[test]
@@ -56,4 +89,10 @@ mod tests {
 
         assert_eq!(registers.get(Register::PC), 0x3000);
     }
+
+    #[test]
+    fn test_jmp_display() {
+        assert_eq!(Jmp::from_bits(0b1100_000_011_000000).to_string(), "JMP R3");
+        assert_eq!(Jmp::from_bits(0b1100_000_111_000000).to_string(), "RET");
+    }
 }
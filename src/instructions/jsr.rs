@@ -1,30 +1,51 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
+    instructions::Location,
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct JsrOffset {
     pc_offset11: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct JsrRegister {
     base_r: Register,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Jsr {
     JsrOffset(JsrOffset),
     JsrRegister(JsrRegister),
 }
 
 impl Jsr {
+    #[cfg(feature = "alloc")]
+    pub fn offset(pc_offset11: i16) -> Result<Self, String> {
+        Ok(Self::JsrOffset(JsrOffset {
+            pc_offset11: fit_signed(pc_offset11.into(), 11)?,
+        }))
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn register(base_r: Register) -> Result<Self, String> {
+        Ok(Self::JsrRegister(JsrRegister {
+            base_r: general_purpose(base_r)?,
+        }))
+    }
+
     pub fn from_bits(bits: u16) -> Self {
         let r = (bits >> 11) & 0x1 == 0;
 
         if r {
-            let base_r = Register::try_from((bits >> 6) & 0b111).unwrap();
+            let base_r = Register::from_bits(bits >> 6);
 
             Self::JsrRegister(JsrRegister { base_r })
         } else {
@@ -34,6 +55,13 @@ impl Jsr {
         }
     }
 
+    pub fn encode(&self) -> u16 {
+        match self {
+            Jsr::JsrOffset(args) => 0b0100 << 12 | 1 << 11 | args.pc_offset11 & 0x7ff,
+            Jsr::JsrRegister(args) => 0b0100 << 12 | (args.base_r as u16) << 6,
+        }
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
         let temp = registers.get(Register::PC);
 
@@ -51,10 +79,36 @@ impl Jsr {
 
         registers.set(Register::R7, temp);
     }
+
+    /// The subroutine called by a `JSR` at `addr`; `JSRR` targets are only known at run time.
+    pub fn target(&self, addr: u16) -> Option<u16> {
+        match self {
+            Jsr::JsrOffset(args) => Some(addr.wrapping_add(1).wrapping_add(args.pc_offset11)),
+            Jsr::JsrRegister(_) => None,
+        }
+    }
+
+    pub(super) fn fmt_at(&self, f: &mut fmt::Formatter, location: Location) -> fmt::Result {
+        match self {
+            Jsr::JsrOffset(args) => {
+                write!(f, "JSR ")?;
+                location.write_target(f, args.pc_offset11)
+            }
+            Jsr::JsrRegister(args) => write!(f, "JSRR {}", args.base_r),
+        }
+    }
+}
+
+impl fmt::Display for Jsr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, Location::default())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::string::ToString;
+
     use super::*;
 
     #[test]
@@ -118,4 +172,13 @@ mod tests {
 
         assert_eq!(registers.get(Register::PC), 0x7000);
     }
+
+    #[test]
+    fn test_jsr_display() {
+        assert_eq!(Jsr::from_bits(0b0100_1_00000000001).to_string(), "JSR #1");
+        assert_eq!(
+            Jsr::from_bits(0b0100_0_00_001_000000).to_string(),
+            "JSRR R1"
+        );
+    }
 }
@@ -1,10 +1,16 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
     memory::Memory,
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Ldr {
     dr: Register,
     base_r: Register,
@@ -12,9 +18,18 @@ pub struct Ldr {
 }
 
 impl Ldr {
+    #[cfg(feature = "alloc")]
+    pub fn new(dr: Register, base_r: Register, offset6: i16) -> Result<Self, String> {
+        Ok(Self {
+            dr: general_purpose(dr)?,
+            base_r: general_purpose(base_r)?,
+            offset6: fit_signed(offset6.into(), 6)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let dr = Register::try_from((bits >> 9) & 0b111).unwrap();
-        let base_r = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let dr = Register::from_bits(bits >> 9);
+        let base_r = Register::from_bits(bits >> 6);
         let offset6 = sign_extend(bits & 0b111111, 6);
 
         Self {
@@ -24,13 +39,32 @@ impl Ldr {
         }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b0110 << 12 | (self.dr as u16) << 9 | (self.base_r as u16) << 6 | self.offset6 & 0b111111
+    }
+
+    /// The address the instruction loads from.
+    pub fn address(&self, registers: &Registers) -> u16 {
+        registers.get(self.base_r).wrapping_add(self.offset6)
+    }
+
     pub fn execute(&self, registers: &mut Registers, memory: &mut Memory) {
-        let val = memory.read(registers.get(self.base_r) + self.offset6);
+        let val = memory.read(self.address(registers));
         registers.set(self.dr, val);
         registers.update_flags(self.dr);
     }
 }
 
+impl fmt::Display for Ldr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LDR {}, {}, #{}",
+            self.dr, self.base_r, self.offset6 as i16
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
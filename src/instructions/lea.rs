@@ -1,24 +1,57 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
+    instructions::Location,
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Lea {
     dr: Register,
     pc_offset9: u16,
 }
 
 impl Lea {
+    #[cfg(feature = "alloc")]
+    pub fn new(dr: Register, pc_offset9: i16) -> Result<Self, String> {
+        Ok(Self {
+            dr: general_purpose(dr)?,
+            pc_offset9: fit_signed(pc_offset9.into(), 9)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let dr = Register::try_from((bits >> 9) & 0b111).unwrap();
+        let dr = Register::from_bits(bits >> 9);
         let pc_offset9 = sign_extend(bits & 0x1ff, 9);
 
         Self { dr, pc_offset9 }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b1110 << 12 | (self.dr as u16) << 9 | self.pc_offset9 & 0x1ff
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
-        registers.set(self.dr, registers.get(Register::PC) + self.pc_offset9);
+        registers.set(
+            self.dr,
+            registers.get(Register::PC).wrapping_add(self.pc_offset9),
+        );
+    }
+
+    pub(super) fn fmt_at(&self, f: &mut fmt::Formatter, location: Location) -> fmt::Result {
+        write!(f, "LEA {}, ", self.dr)?;
+        location.write_target(f, self.pc_offset9)
+    }
+}
+
+impl fmt::Display for Lea {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, Location::default())
     }
 }
 
@@ -28,9 +28,68 @@ pub use sti::Sti;
 pub use str::Str;
 pub use trap::Trap;
 
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt;
+#[cfg(not(feature = "alloc"))]
+use core::marker::PhantomData;
+
 use crate::{memory::Memory, opcodes::Opcode, registers::Registers, traps::TrapCode};
+#[cfg(feature = "alloc")]
+use crate::{protection::Access, registers::Register, symbols::SymbolTable};
+
+/// Fails unless `register` is one of R0-R7, the only registers an instruction can encode.
+#[cfg(feature = "alloc")]
+fn general_purpose(register: Register) -> Result<Register, String> {
+    match register {
+        Register::PC | Register::COND => {
+            Err(format!("{} is not a general purpose register", register))
+        }
+        register => Ok(register),
+    }
+}
 
-#[derive(Debug)]
+/// Where an instruction was fetched from, used to render its PC-relative operands as
+/// resolved addresses (or labels) instead of raw offsets.
+#[derive(Clone, Copy, Default)]
+pub struct Location<'a> {
+    addr: Option<u16>,
+    #[cfg(feature = "alloc")]
+    symbols: Option<&'a SymbolTable>,
+    #[cfg(not(feature = "alloc"))]
+    symbols: PhantomData<&'a ()>,
+}
+
+impl Location<'_> {
+    fn write_target(&self, f: &mut fmt::Formatter, offset: u16) -> fmt::Result {
+        let Some(addr) = self.addr else {
+            return write!(f, "#{}", offset as i16);
+        };
+
+        let target = addr.wrapping_add(1).wrapping_add(offset);
+        #[cfg(feature = "alloc")]
+        if let Some(symbols) = self.symbols {
+            return write!(f, "{}", symbols.format_address(target));
+        }
+        write!(f, "x{:04X}", target)
+    }
+}
+
+/// An instruction displayed as it appears at a given address.
+#[cfg(feature = "alloc")]
+pub struct Disassembly<'a> {
+    instruction: &'a Instruction,
+    location: Location<'a>,
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.instruction.fmt_at(f, self.location)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     Add(Add),
     Br(Br),
@@ -48,13 +107,23 @@ pub enum Instruction {
     And(And),
 }
 
+#[cfg(feature = "alloc")]
 impl TryFrom<u16> for Instruction {
     type Error = String;
 
     fn try_from(bits: u16) -> Result<Self, Self::Error> {
-        let opcode = Opcode::from(bits);
+        Self::decode(bits).ok_or_else(|| match Opcode::from(bits) {
+            Opcode::TRAP => TrapCode::try_from(bits).unwrap_err(),
+            opcode => format!("unsupported opcode {:?}", opcode),
+        })
+    }
+}
 
-        let instruction = match opcode {
+impl Instruction {
+    /// The instruction `bits` encode, or `None` for `RTI`, the reserved opcode and trap
+    /// vectors without a service routine. [`Instruction::try_from`] says which.
+    pub fn decode(bits: u16) -> Option<Self> {
+        let instruction = match Opcode::from(bits) {
             Opcode::BR => Self::Br(Br::from_bits(bits)),
             Opcode::ADD => Self::Add(Add::from_bits(bits)),
             Opcode::JMP => Self::Jmp(Jmp::from_bits(bits)),
@@ -67,16 +136,14 @@ impl TryFrom<u16> for Instruction {
             Opcode::ST => Self::St(St::from_bits(bits)),
             Opcode::STI => Self::Sti(Sti::from_bits(bits)),
             Opcode::STR => Self::Str(Str::from_bits(bits)),
-            Opcode::TRAP => Self::Trap(Trap::from_bits(bits)),
+            Opcode::TRAP => Self::Trap(Trap::new(TrapCode::from_bits(bits)?)),
             Opcode::AND => Self::And(And::from_bits(bits)),
-            _ => unreachable!(),
+            Opcode::RTI | Opcode::RES => return None,
         };
 
-        Ok(instruction)
+        Some(instruction)
     }
-}
 
-impl Instruction {
     pub fn execute(&self, registers: &mut Registers, memory: &mut Memory) {
         match self {
             Instruction::Add(x) => x.execute(registers),
@@ -96,10 +163,269 @@ impl Instruction {
         }
     }
 
-    pub fn is_halt(&self) -> bool {
+    pub fn encode(&self) -> u16 {
         match self {
-            Instruction::Trap(trap) if trap.trap_code == TrapCode::HALT => true,
-            _ => false,
+            Instruction::Add(x) => x.encode(),
+            Instruction::Br(x) => x.encode(),
+            Instruction::Jmp(x) => x.encode(),
+            Instruction::Jsr(x) => x.encode(),
+            Instruction::Ld(x) => x.encode(),
+            Instruction::Ldi(x) => x.encode(),
+            Instruction::Ldr(x) => x.encode(),
+            Instruction::Lea(x) => x.encode(),
+            Instruction::Not(x) => x.encode(),
+            Instruction::St(x) => x.encode(),
+            Instruction::Sti(x) => x.encode(),
+            Instruction::Str(x) => x.encode(),
+            Instruction::Trap(x) => x.encode(),
+            Instruction::And(x) => x.encode(),
+        }
+    }
+
+    /// The memory the instruction reads and writes, in order, when executed with the PC
+    /// past it. Traps are left out, since they run in supervisor mode. The pointer an
+    /// indirect load or store goes through is looked up without polling the keyboard.
+    #[cfg(feature = "alloc")]
+    pub fn accesses(&self, registers: &Registers, memory: &Memory) -> Vec<(u16, Access)> {
+        match self {
+            Instruction::Ld(x) => vec![(x.address(registers), Access::Read)],
+            Instruction::Ldr(x) => vec![(x.address(registers), Access::Read)],
+            Instruction::St(x) => vec![(x.address(registers), Access::Write)],
+            Instruction::Str(x) => vec![(x.address(registers), Access::Write)],
+            Instruction::Ldi(x) => {
+                let pointer = x.address(registers);
+                vec![
+                    (pointer, Access::Read),
+                    (memory.peek(pointer), Access::Read),
+                ]
+            }
+            Instruction::Sti(x) => {
+                let pointer = x.address(registers);
+                vec![
+                    (pointer, Access::Read),
+                    (memory.peek(pointer), Access::Write),
+                ]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The addresses execution can continue at after this instruction at `addr`, as far as
+    /// they can be known without running it. Subroutine calls and traps other than `HALT`
+    /// continue after the instruction once they return.
+    #[cfg(feature = "alloc")]
+    pub fn successors(&self, addr: u16) -> Vec<u16> {
+        let next = addr.wrapping_add(1);
+
+        match self {
+            Instruction::Br(br) if br.is_unconditional() => vec![br.target(addr)],
+            Instruction::Br(br) => vec![next, br.target(addr)],
+            Instruction::Jmp(_) => vec![],
+            Instruction::Jsr(jsr) => core::iter::once(next).chain(jsr.target(addr)).collect(),
+            Instruction::Trap(_) if self.is_halt() => vec![],
+            _ => vec![next],
+        }
+    }
+
+    /// Displays the instruction as located at `addr`, resolving PC-relative operands.
+    #[cfg(feature = "alloc")]
+    pub fn at<'a>(&'a self, addr: u16, symbols: Option<&'a SymbolTable>) -> Disassembly<'a> {
+        Disassembly {
+            instruction: self,
+            location: Location {
+                addr: Some(addr),
+                symbols,
+            },
+        }
+    }
+
+    fn fmt_at(&self, f: &mut fmt::Formatter, location: Location) -> fmt::Result {
+        match self {
+            Instruction::Add(x) => write!(f, "{}", x),
+            Instruction::Br(x) => x.fmt_at(f, location),
+            Instruction::Jmp(x) => write!(f, "{}", x),
+            Instruction::Jsr(x) => x.fmt_at(f, location),
+            Instruction::Ld(x) => x.fmt_at(f, location),
+            Instruction::Ldi(x) => x.fmt_at(f, location),
+            Instruction::Ldr(x) => write!(f, "{}", x),
+            Instruction::Lea(x) => x.fmt_at(f, location),
+            Instruction::Not(x) => write!(f, "{}", x),
+            Instruction::St(x) => x.fmt_at(f, location),
+            Instruction::Sti(x) => x.fmt_at(f, location),
+            Instruction::Str(x) => write!(f, "{}", x),
+            Instruction::Trap(x) => write!(f, "{}", x),
+            Instruction::And(x) => write!(f, "{}", x),
+        }
+    }
+
+    pub fn is_halt(&self) -> bool {
+        matches!(self, Instruction::Trap(trap) if trap.trap_code == TrapCode::HALT)
+    }
+
+    /// Whether this is a subroutine call, `JSR` or `JSRR`.
+    pub fn is_call(&self) -> bool {
+        matches!(self, Instruction::Jsr(_))
+    }
+
+    /// Whether this returns from a subroutine, i.e. `RET` (`JMP R7`).
+    pub fn is_return(&self) -> bool {
+        matches!(self, Instruction::Jmp(jmp) if jmp.is_return())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, Location::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    #[cfg(feature = "alloc")]
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn test_display_pc_relative_as_offset() {
+        // BRnz  #-3
+        let instruction = Instruction::decode(0b0000_110_111111101).unwrap();
+
+        assert_eq!(instruction.to_string(), "BRnz #-3");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_display_pc_relative_at_address() {
+        // BRnz  #15
+        let instruction = Instruction::try_from(0b0000_110_000001111).unwrap();
+
+        assert_eq!(instruction.at(0x3000, None).to_string(), "BRnz x3010");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_display_pc_relative_with_label() {
+        let mut symbols = SymbolTable::default();
+        symbols.insert("LOOP", 0x2FFE);
+
+        // LD   R2, #-3
+        let instruction = Instruction::try_from(0b0010_010_111111101).unwrap();
+
+        assert_eq!(
+            instruction.at(0x3000, Some(&symbols)).to_string(),
+            "LD R2, LOOP"
+        );
+        assert_eq!(
+            instruction.at(0x3002, Some(&symbols)).to_string(),
+            "LD R2, LOOP+2"
+        );
+    }
+
+    /// Whether the bits every decoder ignores are set the way the encoder writes them.
+    fn is_canonical(bits: u16) -> bool {
+        match Opcode::from(bits) {
+            Opcode::ADD | Opcode::AND if bits & (1 << 5) == 0 => bits & 0b11000 == 0,
+            Opcode::NOT => bits & 0b111111 == 0b111111,
+            Opcode::JMP => bits & 0b0000_111_000_111111 == 0,
+            Opcode::JSR if bits & (1 << 11) == 0 => bits & 0b0000_0_11_000_111111 == 0,
+            Opcode::TRAP => bits & 0b0000_1111_0000_0000 == 0,
+            _ => true,
         }
     }
+
+    #[test]
+    fn test_encode_round_trips_every_word() {
+        for bits in 0..=u16::MAX {
+            let Some(instruction) = Instruction::decode(bits) else {
+                continue;
+            };
+
+            let encoded = instruction.encode();
+
+            assert_eq!(
+                Instruction::decode(encoded),
+                Some(instruction),
+                "x{:04X}",
+                bits
+            );
+            if is_canonical(bits) {
+                assert_eq!(encoded, bits, "x{:04X}", bits);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_construct_and_encode() {
+        let instructions = [
+            (
+                Instruction::Add(Add::immediate(Register::R1, Register::R2, -3).unwrap()),
+                0x12BD,
+            ),
+            (
+                Instruction::Br(Br::new(true, true, false, -3).unwrap()),
+                0x0DFD,
+            ),
+            (Instruction::Jmp(Jmp::new(Register::R7).unwrap()), 0xC1C0),
+            (
+                Instruction::Ldr(Ldr::new(Register::R0, Register::R6, 31).unwrap()),
+                0x619F,
+            ),
+            (Instruction::Trap(Trap::new(TrapCode::HALT)), 0xF025),
+        ];
+
+        for (instruction, bits) in instructions {
+            assert_eq!(instruction.encode(), bits);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_constructors_validate_operands() {
+        assert!(Add::immediate(Register::R0, Register::R0, 16).is_err());
+        assert!(Br::new(true, false, false, -257).is_err());
+        assert!(Jsr::offset(1024).is_err());
+        assert!(Not::new(Register::PC, Register::R0).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_successors() {
+        let successors = |bits: u16| Instruction::try_from(bits).unwrap().successors(0x3000);
+
+        // BRz  #-3
+        assert_eq!(successors(0b0000_010_111111101), vec![0x3001, 0x2FFE]);
+        // BRnzp  #2
+        assert_eq!(successors(0b0000_111_000000010), vec![0x3003]);
+        // JSR  #4
+        assert_eq!(successors(0b0100_1_00000000100), vec![0x3001, 0x3005]);
+        // RET
+        assert!(successors(0xC1C0).is_empty());
+        // HALT
+        assert!(successors(0xF025).is_empty());
+        // ADD  R0, R0, R0
+        assert_eq!(successors(0x1000), vec![0x3001]);
+    }
+
+    #[test]
+    fn test_reserved_opcodes_fail_to_decode() {
+        assert!(Instruction::decode(0x8000).is_none());
+        assert!(Instruction::decode(0xD000).is_none());
+        assert!(Instruction::decode(0xF0FF).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_decode_errors() {
+        assert_eq!(
+            Instruction::try_from(0x8000),
+            Err(String::from("unsupported opcode RTI"))
+        );
+        assert_eq!(
+            Instruction::try_from(0xF0FF),
+            Err(String::from("Bad trapcode"))
+        );
+    }
 }
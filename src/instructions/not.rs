@@ -1,25 +1,49 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::instructions::general_purpose;
 use crate::registers::{Register, Registers};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Not {
     dr: Register,
     sr: Register,
 }
 
 impl Not {
+    #[cfg(feature = "alloc")]
+    pub fn new(dr: Register, sr: Register) -> Result<Self, String> {
+        Ok(Self {
+            dr: general_purpose(dr)?,
+            sr: general_purpose(sr)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let dr = Register::try_from((bits >> 9) & 0b111).unwrap();
-        let sr = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let dr = Register::from_bits(bits >> 9);
+        let sr = Register::from_bits(bits >> 6);
 
         Self { dr, sr }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b1001 << 12 | (self.dr as u16) << 9 | (self.sr as u16) << 6 | 0b111111
+    }
+
     pub fn execute(&self, registers: &mut Registers) {
         registers.set(self.dr, !registers.get(self.sr));
         registers.update_flags(self.dr);
     }
 }
 
+impl fmt::Display for Not {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NOT {}, {}", self.dr, self.sr)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
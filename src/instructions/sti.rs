@@ -1,29 +1,64 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
+    instructions::Location,
     memory::Memory,
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Sti {
     sr: Register,
     pc_offset9: u16,
 }
 
 impl Sti {
+    #[cfg(feature = "alloc")]
+    pub fn new(sr: Register, pc_offset9: i16) -> Result<Self, String> {
+        Ok(Self {
+            sr: general_purpose(sr)?,
+            pc_offset9: fit_signed(pc_offset9.into(), 9)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let sr = Register::try_from((bits >> 9) & 0b111).unwrap();
+        let sr = Register::from_bits(bits >> 9);
         let pc_offset9 = sign_extend(bits & 0x1ff, 9);
 
         Self { sr, pc_offset9 }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b1011 << 12 | (self.sr as u16) << 9 | self.pc_offset9 & 0x1ff
+    }
+
+    /// The address of the pointer the instruction stores through, with the PC past it.
+    pub fn address(&self, registers: &Registers) -> u16 {
+        registers.get(Register::PC).wrapping_add(self.pc_offset9)
+    }
+
     pub fn execute(&self, registers: &mut Registers, memory: &mut Memory) {
         let val = registers.get(self.sr);
-        let inner = memory.read(registers.get(Register::PC).wrapping_add(self.pc_offset9));
+        let inner = memory.read(self.address(registers));
 
         memory.write(inner, val);
     }
+
+    pub(super) fn fmt_at(&self, f: &mut fmt::Formatter, location: Location) -> fmt::Result {
+        write!(f, "STI {}, ", self.sr)?;
+        location.write_target(f, self.pc_offset9)
+    }
+}
+
+impl fmt::Display for Sti {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, Location::default())
+    }
 }
 
 #[cfg(test)]
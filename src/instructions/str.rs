@@ -1,10 +1,16 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{instructions::general_purpose, utils::fit_signed};
 use crate::{
     memory::Memory,
     registers::{Register, Registers},
     utils::sign_extend,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Str {
     sr: Register,
     base_r: Register,
@@ -12,9 +18,18 @@ pub struct Str {
 }
 
 impl Str {
+    #[cfg(feature = "alloc")]
+    pub fn new(sr: Register, base_r: Register, offset6: i16) -> Result<Self, String> {
+        Ok(Self {
+            sr: general_purpose(sr)?,
+            base_r: general_purpose(base_r)?,
+            offset6: fit_signed(offset6.into(), 6)?,
+        })
+    }
+
     pub fn from_bits(bits: u16) -> Self {
-        let sr = Register::try_from((bits >> 9) & 0b111).unwrap();
-        let base_r = Register::try_from((bits >> 6) & 0b111).unwrap();
+        let sr = Register::from_bits(bits >> 9);
+        let base_r = Register::from_bits(bits >> 6);
         let pc_offset6 = sign_extend(bits & 0b111111, 6);
 
         Self {
@@ -24,9 +39,28 @@ impl Str {
         }
     }
 
+    pub fn encode(&self) -> u16 {
+        0b0111 << 12 | (self.sr as u16) << 9 | (self.base_r as u16) << 6 | self.offset6 & 0b111111
+    }
+
+    /// The address the instruction stores to.
+    pub fn address(&self, registers: &Registers) -> u16 {
+        registers.get(self.base_r).wrapping_add(self.offset6)
+    }
+
     pub fn execute(&self, registers: &mut Registers, memory: &mut Memory) {
         let val = registers.get(self.sr);
-        memory.write(registers.get(self.base_r).wrapping_add(self.offset6), val);
+        memory.write(self.address(registers), val);
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "STR {}, {}, #{}",
+            self.sr, self.base_r, self.offset6 as i16
+        )
     }
 }
 
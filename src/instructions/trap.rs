@@ -1,8 +1,6 @@
-use std::{
-    char,
-    io::{self, Read, Write},
-    process,
-};
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::{char, fmt};
 
 use crate::{
     memory::Memory,
@@ -10,71 +8,84 @@ use crate::{
     traps::TrapCode,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Trap {
     pub trap_code: TrapCode,
 }
 
 impl Trap {
-    pub fn from_bits(bits: u16) -> Self {
-        let trap_code = TrapCode::try_from(bits).unwrap();
-
+    pub fn new(trap_code: TrapCode) -> Self {
         Self { trap_code }
     }
 
+    #[cfg(feature = "alloc")]
+    pub fn from_bits(bits: u16) -> Result<Self, String> {
+        let trap_code = TrapCode::try_from(bits)?;
+
+        Ok(Self { trap_code })
+    }
+
+    pub fn encode(&self) -> u16 {
+        0b1111 << 12 | self.trap_code as u16
+    }
+
     pub fn execute(&self, registers: &mut Registers, memory: &mut Memory) {
         registers.set(Register::R7, registers.get(Register::PC));
 
         match self.trap_code {
             TrapCode::GETC => {
-                let mut buffer = [0; 1];
-                std::io::stdin().read_exact(&mut buffer).unwrap();
-                registers.set(Register::R0, buffer[0] as u16);
-            }
-            TrapCode::OUT => {
-                let c = char::from((registers.get(Register::R0) & 0xff) as u8);
-                print!("{}", c);
+                let key = memory.read_key().unwrap_or(0);
+                registers.set(Register::R0, key as u16);
             }
+            TrapCode::OUT => write_char(memory, (registers.get(Register::R0) & 0xff) as u8),
             TrapCode::PUTS => {
-                let mut index = registers.get(Register::R0);
-                let mut c = (memory.read(index) & 0xff) as u8;
-                while c != 0x0000 {
-                    print!("{}", c as char);
-                    index += 1;
-                    c = (memory.read(index) & 0xff) as u8;
+                let start = registers.get(Register::R0);
+                // A string with no terminator ends once it has wrapped around memory.
+                for offset in 0..=u16::MAX {
+                    let c = (memory.read(start.wrapping_add(offset)) & 0xff) as u8;
+                    if c == 0 {
+                        break;
+                    }
+                    write_char(memory, c);
                 }
-                io::stdout().flush().expect("failed to flush");
+                memory.console().flush();
             }
             TrapCode::IN => {
-                print!("Enter a  character : ");
-                io::stdout().flush().expect("failed to flush");
-                let char = std::io::stdin()
-                    .bytes()
-                    .next()
-                    .and_then(|result| result.ok())
-                    .map(|byte| byte as u16)
-                    .unwrap();
-                registers.set(Register::R0, char);
+                memory.console().write("Enter a  character : ");
+                memory.console().flush();
+                let key = memory.read_key().unwrap_or(0);
+                registers.set(Register::R0, key as u16);
             }
             TrapCode::PUTSP => {
-                let mut index = registers.get(Register::R0);
-                let mut c = memory.read(index);
-                while c != 0x0000 {
-                    let c1 = ((c & 0xFF) as u8) as char;
-                    print!("{}", c1);
-                    let c2 = ((c >> 8) as u8) as char;
-                    if c2 != '\0' {
-                        print!("{}", c2);
+                let start = registers.get(Register::R0);
+                for offset in 0..=u16::MAX {
+                    let c = memory.read(start.wrapping_add(offset));
+                    if c == 0x0000 {
+                        break;
+                    }
+                    write_char(memory, (c & 0xFF) as u8);
+                    let c2 = (c >> 8) as u8;
+                    if c2 != 0 {
+                        write_char(memory, c2);
                     }
-                    index += 1;
-                    c = memory.read(index);
                 }
-                io::stdout().flush().expect("failed to flush");
-            }
-            TrapCode::HALT => {
-                io::stdout().flush().expect("failed to flush");
-                process::exit(1);
+                memory.console().flush();
             }
+            // Stopping at HALT is up to the caller, as `Vm` does.
+            TrapCode::HALT => memory.console().flush(),
         }
     }
 }
+
+/// Writes the character with code `c` to the console, with no string to collect it in.
+fn write_char(memory: &mut Memory, c: u8) {
+    memory
+        .console()
+        .write(char::from(c).encode_utf8(&mut [0; 4]));
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TRAP x{:02X}", self.trap_code as u16)
+    }
+}
@@ -0,0 +1,55 @@
+// Register, opcode and trap names follow the LC-3 ISA mnemonics, and binary literals are
+// grouped by instruction field rather than by nibble.
+#![allow(clippy::upper_case_acronyms, clippy::unusual_byte_groupings)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(any(feature = "alloc", test))]
+extern crate alloc;
+
+pub mod console;
+pub mod instructions;
+pub mod memory;
+pub mod opcodes;
+pub mod registers;
+pub mod traps;
+pub mod utils;
+
+#[cfg(feature = "alloc")]
+pub mod engine;
+#[cfg(feature = "alloc")]
+pub mod image;
+#[cfg(feature = "alloc")]
+pub mod loader;
+#[cfg(feature = "alloc")]
+pub mod objinfo;
+#[cfg(feature = "alloc")]
+pub mod protection;
+#[cfg(feature = "alloc")]
+pub mod symbols;
+#[cfg(feature = "alloc")]
+pub mod vm;
+
+#[cfg(feature = "std")]
+pub mod assembler;
+#[cfg(feature = "spec")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod flamegraph;
+#[cfg(feature = "std")]
+pub mod formats;
+#[cfg(feature = "std")]
+pub mod linker;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "spec")]
+pub mod spec;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+#[cfg(feature = "tui")]
+pub mod tui;
@@ -0,0 +1,411 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{assembler::Assembly, image::Image, symbols::SymbolTable, utils::fit_signed};
+
+const MAGIC: &str = "LC3REL";
+
+/// How a word in a module has to be patched once its final address is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    /// The whole word is an address: add the module base, or the symbol's address.
+    Absolute,
+    /// The low 9 bits are a PC-relative offset to the symbol (`BR`, `LD`, `LEA`, ...).
+    PcOffset9,
+    /// The low 11 bits are a PC-relative offset to the symbol (`JSR`).
+    PcOffset11,
+}
+
+impl RelocationKind {
+    fn bits(self) -> u8 {
+        match self {
+            RelocationKind::Absolute => 16,
+            RelocationKind::PcOffset9 => 9,
+            RelocationKind::PcOffset11 => 11,
+        }
+    }
+}
+
+impl FromStr for RelocationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ABS16" => Ok(RelocationKind::Absolute),
+            "PC9" => Ok(RelocationKind::PcOffset9),
+            "PC11" => Ok(RelocationKind::PcOffset11),
+            _ => Err(format!("unknown relocation kind '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for RelocationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelocationKind::Absolute => write!(f, "ABS16"),
+            RelocationKind::PcOffset9 => write!(f, "PC9"),
+            RelocationKind::PcOffset11 => write!(f, "PC11"),
+        }
+    }
+}
+
+/// A word at `offset` in a module that depends on where modules end up in memory.
+///
+/// Without a symbol the word refers to the module itself and is moved with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocationKind,
+    pub symbol: Option<String>,
+}
+
+/// A relocatable object: code assembled at offset 0 plus what the linker needs to place it.
+///
+/// Modules are stored as text:
+///
+/// ```text
+/// LC3REL
+/// SYMBOL MAIN x0000 GLOBAL
+/// SYMBOL LOOP x0003
+/// EXTERNAL PRINT
+/// RELOC x0005 PC11 PRINT
+/// RELOC x0007 ABS16
+/// CODE x0008
+/// xE002
+/// ...
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Module {
+    pub words: Vec<u16>,
+    /// Every label of the module, as an offset from its first word.
+    pub symbols: SymbolTable,
+    /// Labels other modules may refer to.
+    pub globals: Vec<String>,
+    /// Labels this module uses but another module defines.
+    pub externals: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Module {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut module = Module::default();
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == MAGIC => {}
+            _ => return Err("not a relocatable module".to_string()),
+        }
+
+        let mut code_len = None;
+        for (line, text) in lines.by_ref() {
+            let at_line = |e: String| format!("line {}: {}", line, e);
+            let fields: Vec<&str> = text.split_whitespace().collect();
+
+            match fields[..] {
+                [] => {}
+                ["SYMBOL", label, offset, ref rest @ ..] => {
+                    module
+                        .symbols
+                        .insert(label, parse_word(offset).map_err(at_line)?);
+                    match rest {
+                        [] => {}
+                        ["GLOBAL"] => module.globals.push(label.to_string()),
+                        _ => return Err(at_line(format!("invalid symbol '{}'", text.trim()))),
+                    }
+                }
+                ["EXTERNAL", label] => module.externals.push(label.to_string()),
+                ["RELOC", offset, kind, ref symbol @ ..] => {
+                    let kind = kind.parse().map_err(at_line)?;
+                    let symbol = match symbol {
+                        [] if kind != RelocationKind::Absolute => {
+                            return Err(at_line(format!("{} relocation needs a symbol", kind)))
+                        }
+                        [] => None,
+                        [symbol] => Some(symbol.to_string()),
+                        _ => return Err(at_line(format!("invalid relocation '{}'", text.trim()))),
+                    };
+                    module.relocations.push(Relocation {
+                        offset: parse_word(offset).map_err(at_line)?,
+                        kind,
+                        symbol,
+                    });
+                }
+                ["CODE", len] => {
+                    code_len = Some(parse_word(len).map_err(at_line)? as usize);
+                    break;
+                }
+                _ => return Err(at_line(format!("unexpected '{}'", text.trim()))),
+            }
+        }
+
+        let code_len = code_len.ok_or_else(|| "missing CODE section".to_string())?;
+        for (line, text) in lines {
+            if !text.trim().is_empty() {
+                let word = parse_word(text.trim()).map_err(|e| format!("line {}: {}", line, e))?;
+                module.words.push(word);
+            }
+        }
+        if module.words.len() != code_len {
+            return Err(format!(
+                "expected {} words of code, found {}",
+                code_len,
+                module.words.len()
+            ));
+        }
+
+        for relocation in &module.relocations {
+            if relocation.offset as usize >= module.words.len() {
+                return Err(format!(
+                    "relocation at x{:04X} is past the end of the code",
+                    relocation.offset
+                ));
+            }
+        }
+
+        Ok(module)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes the module in the text format read by [`Module::parse`].
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", MAGIC)?;
+
+        let mut symbols: Vec<(&str, u16)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|&(label, offset)| (offset, label));
+        for (label, offset) in symbols {
+            if self.globals.iter().any(|global| global == label) {
+                writeln!(out, "SYMBOL {} x{:04X} GLOBAL", label, offset)?;
+            } else {
+                writeln!(out, "SYMBOL {} x{:04X}", label, offset)?;
+            }
+        }
+        for external in &self.externals {
+            writeln!(out, "EXTERNAL {}", external)?;
+        }
+        for relocation in &self.relocations {
+            write!(out, "RELOC x{:04X} {}", relocation.offset, relocation.kind)?;
+            match &relocation.symbol {
+                Some(symbol) => writeln!(out, " {}", symbol)?,
+                None => writeln!(out)?,
+            }
+        }
+
+        writeln!(out, "CODE x{:04X}", self.words.len())?;
+        for word in &self.words {
+            writeln!(out, "x{:04X}", word)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Places `modules` one after another from `origin`, resolves the symbols they share and
+/// patches their relocations into a single image.
+///
+/// Each module is paired with a name used in error messages. The symbol table holds every
+/// global label, plus local labels whose name no other module has already used.
+pub fn link(modules: &[(&str, Module)], origin: u16) -> Result<Assembly, String> {
+    let mut bases = Vec::with_capacity(modules.len());
+    let mut next = origin as usize;
+    for (_, module) in modules {
+        bases.push(next as u16);
+        next += module.words.len();
+    }
+    if next > 0x10000 {
+        return Err(format!(
+            "modules do not fit in memory starting at x{:04X}",
+            origin
+        ));
+    }
+
+    let mut globals = SymbolTable::default();
+    for ((name, module), &base) in modules.iter().zip(&bases) {
+        for global in &module.globals {
+            let offset = module
+                .symbols
+                .address(global)
+                .ok_or_else(|| format!("{}: global '{}' is not defined", name, global))?;
+            if globals.address(global).is_some() {
+                return Err(format!(
+                    "{}: '{}' is defined by more than one module",
+                    name, global
+                ));
+            }
+            globals.insert(global, base.wrapping_add(offset));
+        }
+    }
+
+    let mut words = Vec::with_capacity(next - origin as usize);
+    for ((name, module), &base) in modules.iter().zip(&bases) {
+        let start = words.len();
+        words.extend_from_slice(&module.words);
+
+        for relocation in &module.relocations {
+            let word = &mut words[start + relocation.offset as usize];
+            let addr = base.wrapping_add(relocation.offset);
+
+            let target = match &relocation.symbol {
+                Some(symbol) => globals
+                    .address(symbol)
+                    .ok_or_else(|| format!("{}: undefined symbol '{}'", name, symbol))?,
+                None => base,
+            };
+
+            *word = match relocation.kind {
+                RelocationKind::Absolute => word.wrapping_add(target),
+                kind => {
+                    let offset = target as i32 - (addr as i32 + 1);
+                    let mask = (1 << kind.bits()) - 1;
+                    let offset = fit_signed(offset, kind.bits()).map_err(|e| {
+                        format!(
+                            "{}: reference at x{:04X} cannot reach x{:04X}: {}",
+                            name, addr, target, e
+                        )
+                    })?;
+
+                    (*word & !mask) | (offset & mask)
+                }
+            };
+        }
+    }
+
+    let mut symbols = globals;
+    for ((_, module), &base) in modules.iter().zip(&bases) {
+        for (label, offset) in module.symbols.iter() {
+            if symbols.address(label).is_none() {
+                symbols.insert(label, base.wrapping_add(offset));
+            }
+        }
+    }
+
+    Ok(Assembly {
+        image: Image { origin, words },
+        symbols,
+        lines: BTreeMap::new(),
+    })
+}
+
+fn parse_word(word: &str) -> Result<u16, String> {
+    word.strip_prefix(['x', 'X'])
+        .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| format!("invalid word '{}'", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_module;
+
+    #[test]
+    fn test_module_round_trips() {
+        let module = assemble_module(
+            "
+            .EXTERNAL PRINT
+            .GLOBAL MAIN
+            .ORIG x0
+            MAIN JSR PRINT
+            LD R0, PTR
+            HALT
+            PTR .FILL MAIN
+            EXT .FILL PRINT
+            .END
+            ",
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        module.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            text,
+            "LC3REL\n\
+             SYMBOL MAIN x0000 GLOBAL\n\
+             SYMBOL PTR x0003\n\
+             SYMBOL EXT x0004\n\
+             EXTERNAL PRINT\n\
+             RELOC x0000 PC11 PRINT\n\
+             RELOC x0003 ABS16\n\
+             RELOC x0004 ABS16 PRINT\n\
+             CODE x0005\n\
+             x4800\n\
+             x2001\n\
+             xF025\n\
+             x0000\n\
+             x0000\n"
+        );
+        assert_eq!(Module::parse(&text).unwrap(), module);
+    }
+
+    #[test]
+    fn test_link_modules() {
+        let main = assemble_module(
+            "
+            .EXTERNAL PRINT
+            .ORIG x0
+            MAIN JSR PRINT
+            LD R0, PTR
+            HALT
+            PTR .FILL MAIN
+            .END
+            ",
+        )
+        .unwrap();
+        let print = assemble_module(
+            "
+            .GLOBAL PRINT
+            .ORIG x0
+            PRINT PUTS
+            RET
+            .END
+            ",
+        )
+        .unwrap();
+
+        let assembly = link(&[("main", main), ("print", print)], 0x3000).unwrap();
+
+        assert_eq!(assembly.image.origin, 0x3000);
+        assert_eq!(
+            assembly.image.words,
+            vec![0x4803, 0x2001, 0xF025, 0x3000, 0xF022, 0xC1C0]
+        );
+        assert_eq!(assembly.symbols.address("PRINT"), Some(0x3004));
+        assert_eq!(assembly.symbols.address("PTR"), Some(0x3003));
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let main = assemble_module(".EXTERNAL FOO\n.ORIG x0\nJSR FOO\n.END").unwrap();
+        assert_eq!(
+            link(&[("main", main.clone())], 0x3000).unwrap_err(),
+            "main: undefined symbol 'FOO'"
+        );
+
+        let foo = assemble_module(".GLOBAL FOO\n.ORIG x0\nFOO RET\n.END").unwrap();
+        assert!(link(&[("a", foo.clone()), ("b", foo.clone())], 0x3000)
+            .unwrap_err()
+            .contains("more than one module"));
+
+        let far = Module {
+            words: vec![0; 0x400],
+            ..Module::default()
+        };
+        let ld = assemble_module(".EXTERNAL FOO\n.ORIG x0\nLD R0, FOO\n.END").unwrap();
+        assert!(link(&[("ld", ld), ("far", far), ("foo", foo)], 0x3000)
+            .unwrap_err()
+            .contains("cannot reach"));
+    }
+}
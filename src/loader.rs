@@ -0,0 +1,102 @@
+use alloc::vec::Vec;
+
+use crate::{image::Image, memory::Memory};
+
+/// A range of addresses written by two different images.
+#[derive(Debug, PartialEq)]
+pub struct Overlap {
+    /// Index of the image loaded first.
+    pub first: usize,
+    /// Index of the image loaded later, whose words end up in memory.
+    pub second: usize,
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Every pair of images that write to the same addresses.
+pub fn find_overlaps(images: &[Image]) -> Vec<Overlap> {
+    let mut overlaps = Vec::new();
+
+    for (second, b) in images.iter().enumerate() {
+        for (first, a) in images[..second].iter().enumerate() {
+            let (Some(a_end), Some(b_end)) = (a.end(), b.end()) else {
+                continue;
+            };
+
+            let start = a.origin.max(b.origin);
+            let end = a_end.min(b_end);
+            if start <= end {
+                overlaps.push(Overlap {
+                    first,
+                    second,
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+
+    overlaps
+}
+
+/// Loads the images in order, so later images win where they overlap. The origin of the
+/// first one becomes the [entry point](Memory::entry).
+pub fn load(memory: &mut Memory, images: &[Image]) {
+    for image in images {
+        memory.load(image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn image(origin: u16, len: usize) -> Image {
+        Image {
+            origin,
+            words: vec![origin; len],
+        }
+    }
+
+    #[test]
+    fn test_disjoint_images_do_not_overlap() {
+        let images = [image(0x0200, 0x100), image(0x3000, 0x10), image(0x3010, 4)];
+
+        assert!(find_overlaps(&images).is_empty());
+    }
+
+    #[test]
+    fn test_find_overlaps() {
+        let images = [image(0x3000, 0x10), image(0x4000, 1), image(0x300A, 0x10)];
+
+        assert_eq!(
+            find_overlaps(&images),
+            vec![Overlap {
+                first: 0,
+                second: 2,
+                start: 0x300A,
+                end: 0x300F,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_empty_images_do_not_overlap() {
+        let images = [image(0x3000, 0x10), image(0x3000, 0)];
+
+        assert!(find_overlaps(&images).is_empty());
+    }
+
+    #[test]
+    fn test_later_images_win() {
+        let mut memory = Memory::default();
+
+        load(&mut memory, &[image(0x3000, 2), image(0x3001, 1)]);
+
+        assert_eq!(memory.entry(), Some(0x3000));
+        assert_eq!(memory.read(0x3000), 0x3000);
+        assert_eq!(memory.read(0x3001), 0x3001);
+    }
+}
@@ -1,73 +1,845 @@
-use std::{fs::File, path::PathBuf};
-use termios::{
-    tcsetattr, Termios, BRKINT, ECHO, ICANON, ICRNL, IGNBRK, IGNCR, INLCR, ISTRIP, IXON, PARMRK,
-    TCSANOW,
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
 };
 
-use clap::{value_parser, Arg, Command};
-use memory::Memory;
-use registers::Registers;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+#[cfg(feature = "tui")]
+use lc3_vm::tui;
+use lc3_vm::{
+    assembler::{self, Assembly},
+    batch,
+    coverage::{self, LineMap},
+    disasm,
+    engine::Engine,
+    formats::{self, Format},
+    image::Image,
+    linker::{self, Module},
+    loader,
+    memory::Memory,
+    monitor::{self, Action},
+    objinfo,
+    profile::Profile,
+    protection::{Protection, ProtectionMap},
+    registers::Registers,
+    spec::{self, Outcome, Spec},
+    symbols::SymbolTable,
+    terminal::RawMode,
+    vm::{Stop, Vm},
+};
+
+fn main() {
+    let command = Command::new("lc3-vm")
+        .about("An LC-3 virtual machine")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .arg(
+            Arg::new("image")
+                .short('i')
+                .long("img")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append)
+                .required(true)
+                .help("The path to an object file; repeat to load several, in order"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(|s: &str| s.parse::<Format>())
+                .help("The format of the object files: obj, hex, bin or ihex [default: detected]"),
+        )
+        .arg(
+            Arg::new("start_image")
+                .long("start-image")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("pc")
+                .help("Starts executing at the origin of this image, one of the --img paths"),
+        )
+        .arg(
+            Arg::new("pc").long("pc").help(
+                "Starts executing at this address or label [default: origin of the first image]",
+            ),
+        )
+        .arg(
+            Arg::new("registers")
+                .short('r')
+                .long("reg")
+                .action(ArgAction::Append)
+                .help("Sets an initial register value, e.g. R6=xFE00 or R1=DATA; can be repeated"),
+        )
+        .arg(
+            Arg::new("psr")
+                .long("psr")
+                .help("Sets the initial processor status register, e.g. x8002 [default: x0002]"),
+        )
+        .arg(
+            Arg::new("read_only")
+                .long("read-only")
+                .action(ArgAction::Append)
+                .help("Fails any write from FROM to TO, e.g. MAIN:DATA-1 or x3000; can be repeated"),
+        )
+        .arg(
+            Arg::new("no_execute")
+                .long("no-execute")
+                .action(ArgAction::Append)
+                .help("Fails any instruction run from FROM to TO, e.g. DATA:xFDFF; can be repeated"),
+        )
+        .arg(
+            Arg::new("allow_overlap")
+                .long("allow-overlap")
+                .action(ArgAction::SetTrue)
+                .help("Warns instead of failing when images overlap; later images win"),
+        )
+        .arg(
+            Arg::new("breakpoints")
+                .short('b')
+                .long("break")
+                .action(ArgAction::Append)
+                .help("Stops at this address, e.g. LOOP or x3010, in the monitor; can be repeated. Ctrl-C also stops there"),
+        )
+        .arg(
+            Arg::new("engine")
+                .long("engine")
+                .value_parser(|s: &str| s.parse::<Engine>())
+                .default_value("interpreter")
+                .help("How to execute instructions: interpreter, or blocks to run translated basic blocks"),
+        )
+        .arg(
+            Arg::new("symbols")
+                .short('s')
+                .long("sym")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append)
+                .help("The path to a symbol file [default: <image>.sym, for each image]"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes every executed instruction to this file"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Prints hot spots, opcode counts, memory accesses and subroutine costs at halt",
+                ),
+        )
+        .arg(
+            Arg::new("profile_json")
+                .long("profile-json")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes the profile to this file as JSON"),
+        )
+        .arg(
+            Arg::new("flamegraph")
+                .long("flamegraph")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes the subroutine call stacks to this file as folded stacks"),
+        )
+        .arg(
+            Arg::new("chrome_trace")
+                .long("chrome-trace")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes every subroutine call to this file as Chrome trace events"),
+        )
+        .arg(
+            Arg::new("coverage")
+                .long("coverage")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes which lines and branches executed to this file in lcov format"),
+        )
+        .arg(
+            Arg::new("coverage_html")
+                .long("coverage-html")
+                .value_parser(value_parser!(PathBuf))
+                .help("Writes the coverage to this file as an HTML page"),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append)
+                .help("The assembly source of an image, to report coverage by source line"),
+        )
+        .subcommand(
+            Command::new("asm")
+                .about("Assembles LC-3 assembly into an object file and a symbol file")
+                .arg(
+                    Arg::new("source")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the assembly source"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("The path to the object file [default: <source>.obj or .rel]"),
+                )
+                .arg(
+                    Arg::new("relocatable")
+                        .long("relocatable")
+                        .action(ArgAction::SetTrue)
+                        .help("Writes a relocatable module for `link` instead of an image"),
+                ),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Links relocatable modules into an object file and a symbol file")
+                .arg(
+                    Arg::new("modules")
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .help("The paths to the modules, placed in memory in this order"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the object file"),
+                )
+                .arg(
+                    Arg::new("origin")
+                        .long("origin")
+                        .default_value("x3000")
+                        .help("The address of the first module"),
+                ),
+        )
+        .subcommand(
+            Command::new("disasm")
+                .about("Prints an assembly listing of an object file")
+                .arg(
+                    Arg::new("object")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the object file"),
+                )
+                .arg(
+                    Arg::new("symbols")
+                        .short('s')
+                        .long("sym")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("The path to the symbol file [default: <object>.sym, if present]"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(|s: &str| s.parse::<Format>())
+                        .help("The input format: obj, hex, bin or ihex [default: detected]"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .help("First address to list, e.g. x3010, MAIN or MAIN+4"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Last address to list, e.g. x3020, DONE or DONE-1"),
+                ),
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Converts object files between formats, or exports a range of memory")
+                .arg(
+                    Arg::new("input")
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .help("The paths to the images, loaded in order"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to write to"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(|s: &str| s.parse::<Format>())
+                        .help("The input format: obj, hex, bin or ihex [default: detected]"),
+                )
+                .arg(
+                    Arg::new("output_format")
+                        .long("output-format")
+                        .value_parser(|s: &str| s.parse::<Format>())
+                        .help("The output format [default: from the output extension, or obj]"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .help("Exports memory from this address [default: lowest origin]"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Exports memory up to this address [default: highest end]"),
+                ),
+        )
+        .subcommand(
+            Command::new("objinfo")
+                .about("Checks an object file and summarizes its contents without running it")
+                .arg(
+                    Arg::new("object")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the object file"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(|s: &str| s.parse::<Format>())
+                        .help("The input format: obj, hex, bin or ihex [default: detected]"),
+                ),
+        )
+        .subcommand(
+            Command::new("test")
+                .about("Runs the cases in a TOML or YAML test spec and reports which passed")
+                .arg(
+                    Arg::new("spec")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the spec; image paths in it are relative to it"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Writes the results to this file as JSON"),
+                )
+                .arg(
+                    Arg::new("junit")
+                        .long("junit")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Writes the results to this file as JUnit XML"),
+                ),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Grades many object files against a test spec in parallel")
+                .arg(
+                    Arg::new("spec")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The path to the spec; its images are loaded after each submission"),
+                )
+                .arg(
+                    Arg::new("submissions")
+                        .value_parser(value_parser!(PathBuf))
+                        .num_args(1..)
+                        .required(true)
+                        .help("The object files to grade"),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .value_parser(value_parser!(usize))
+                        .help("How many programs to run at once [default: the number of CPUs]"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_parser(value_parser!(f64))
+                        .default_value("10")
+                        .help("Seconds each case may run before it fails"),
+                )
+                .arg(
+                    Arg::new("results")
+                        .long("results")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Writes <submission>.json and <submission>.xml results to this directory"),
+                ),
+        );
+    #[cfg(feature = "tui")]
+    let command =
+        command.arg(Arg::new("tui").long("tui").action(ArgAction::SetTrue).help(
+            "Debugs the program full screen, with registers, code, memory, calls and output",
+        ));
+    let matches = command.get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("asm", sub_matches)) => asm(sub_matches),
+        Some(("link", sub_matches)) => link(sub_matches),
+        Some(("disasm", sub_matches)) => disasm(sub_matches),
+        Some(("convert", sub_matches)) => convert(sub_matches),
+        Some(("objinfo", sub_matches)) => objinfo(sub_matches),
+        Some(("test", sub_matches)) => test(sub_matches),
+        Some(("batch", sub_matches)) => batch(sub_matches),
+        _ => run(&matches),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let obj_files: Vec<&PathBuf> = matches.get_many::<PathBuf>("image").unwrap().collect();
+    let format = matches.get_one::<Format>("format").copied();
+
+    // Intel HEX files can hold several images, so remember which file each came from.
+    let mut images = Vec::new();
+    let mut image_files = Vec::new();
+    for &obj_file in &obj_files {
+        for image in formats::read_file(obj_file, format)? {
+            images.push(image);
+            image_files.push(obj_file);
+        }
+    }
+
+    for overlap in loader::find_overlaps(&images) {
+        let message = format!(
+            "{} and {} overlap at x{:04X}-x{:04X}",
+            image_files[overlap.first].display(),
+            image_files[overlap.second].display(),
+            overlap.start,
+            overlap.end
+        );
+        if !matches.get_flag("allow_overlap") {
+            return Err(message);
+        }
+        eprintln!("warning: {}", message);
+    }
+
+    let mut memory = Memory::default();
+    loader::load(&mut memory, &images);
+
+    let registers = Registers::default();
+    let mut vm = Vm::new(registers, memory);
 
-use vm::Vm;
+    let mut symbols = SymbolTable::default();
+    for obj_file in &obj_files {
+        if let Some(image_symbols) = load_symbols(obj_file, matches)? {
+            symbols.merge(&image_symbols);
+        }
+    }
 
-mod instructions;
-mod memory;
-mod opcodes;
-mod registers;
-mod traps;
-mod utils;
-mod vm;
+    if let Some(pc) = matches.get_one::<String>("pc") {
+        vm.set_pc(symbols.resolve(pc)?);
+    } else if let Some(start_image) = matches.get_one::<PathBuf>("start_image") {
+        let index = image_files
+            .iter()
+            .position(|&obj_file| obj_file == start_image)
+            .ok_or_else(|| format!("{} is not one of the images", start_image.display()))?;
+        vm.set_pc(images[index].origin);
+    }
 
-pub const STDIN: i32 = 0;
+    for assignment in matches.get_many::<String>("registers").unwrap_or_default() {
+        let (register, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("expected <register>=<value>, got '{}'", assignment))?;
+        vm.set_register(register.parse()?, symbols.resolve(value)?);
+    }
 
-struct TermiosHandler(Termios);
+    if let Some(psr) = matches.get_one::<String>("psr") {
+        vm.set_psr(symbols.resolve(psr)?)?;
+    }
 
-impl TermiosHandler {
-    pub fn new(termios: Termios) -> TermiosHandler {
-        println!("terminal settings modified");
-        let mut new_termios = termios;
-        new_termios.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
-        new_termios.c_lflag &= !(ICANON | ECHO);
+    let mut protection = ProtectionMap::default();
+    for (id, kind) in [
+        ("read_only", Protection::ReadOnly),
+        ("no_execute", Protection::NoExecute),
+    ] {
+        for range in matches.get_many::<String>(id).unwrap_or_default() {
+            let (from, to) = range.split_once(':').unwrap_or((range, range));
+            protection.protect(symbols.resolve(from)?, symbols.resolve(to)?, kind)?;
+        }
+    }
+    vm.set_protection(protection);
 
-        tcsetattr(STDIN, TCSANOW, &new_termios).unwrap();
+    let coverage = matches.get_one::<PathBuf>("coverage");
+    let coverage_html = matches.get_one::<PathBuf>("coverage_html");
+    let line_maps = if coverage.is_some() || coverage_html.is_some() {
+        vm.enable_coverage();
+        line_maps(matches, &images, &image_files, &symbols)?
+    } else {
+        Vec::new()
+    };
 
-        TermiosHandler(termios)
+    for addr in matches
+        .get_many::<String>("breakpoints")
+        .unwrap_or_default()
+    {
+        vm.add_breakpoint(symbols.resolve(addr)?);
     }
+
+    vm.set_symbols(symbols);
+    vm.set_engine(*matches.get_one::<Engine>("engine").unwrap());
+
+    if let Some(trace_file) = matches.get_one::<PathBuf>("trace") {
+        let trace =
+            File::create(trace_file).map_err(|e| format!("{}: {}", trace_file.display(), e))?;
+        vm.set_trace(Box::new(BufWriter::new(trace)));
+    }
+
+    let profile_json = matches.get_one::<PathBuf>("profile_json");
+    let flamegraph = matches.get_one::<PathBuf>("flamegraph");
+    let chrome_trace = matches.get_one::<PathBuf>("chrome_trace");
+    if chrome_trace.is_some() {
+        vm.set_profile(Profile::with_spans());
+    } else if matches.get_flag("profile") || profile_json.is_some() || flamegraph.is_some() {
+        vm.enable_profile();
+    }
+
+    #[cfg(feature = "tui")]
+    let result = if matches.get_flag("tui") {
+        tui::run(&mut vm)
+    } else {
+        run_with_monitor(&mut vm)
+    };
+    #[cfg(not(feature = "tui"))]
+    let result = run_with_monitor(&mut vm);
+
+    // The profile is still worth reading when the program stopped on an error.
+    if matches.get_flag("profile") {
+        vm.write_profile(&mut io::stderr().lock())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(profile_json) = profile_json {
+        let json = vm.profile_json().unwrap();
+        fs::write(profile_json, format!("{:#}\n", json))
+            .map_err(|e| format!("{}: {}", profile_json.display(), e))?;
+    }
+    if let Some(flamegraph) = flamegraph {
+        vm.write_folded_stacks(&mut create(flamegraph)?)
+            .map_err(|e| format!("{}: {}", flamegraph.display(), e))?;
+    }
+    if let Some(coverage) = coverage {
+        coverage::write_lcov(&mut create(coverage)?, vm.coverage().unwrap(), &line_maps)
+            .map_err(|e| format!("{}: {}", coverage.display(), e))?;
+    }
+    if let Some(coverage_html) = coverage_html {
+        coverage::write_html(
+            &mut create(coverage_html)?,
+            vm.coverage().unwrap(),
+            &line_maps,
+        )
+        .map_err(|e| format!("{}: {}", coverage_html.display(), e))?;
+    }
+    if let Some(chrome_trace) = chrome_trace {
+        let json = vm.chrome_trace().unwrap();
+        fs::write(chrome_trace, format!("{}\n", json))
+            .map_err(|e| format!("{}: {}", chrome_trace.display(), e))?;
+    }
+    if result? {
+        println!("execution finished ok");
+    }
+
+    Ok(())
 }
 
-impl Drop for TermiosHandler {
-    fn drop(&mut self) {
-        println!("terminal settings restored");
-        tcsetattr(STDIN, TCSANOW, &self.0).unwrap();
+/// Runs `vm` with the terminal in raw mode, dropping into the monitor at breakpoints and on
+/// Ctrl-C. Returns whether the program halted, rather than being quit from the monitor.
+fn run_with_monitor(vm: &mut Vm) -> Result<bool, String> {
+    loop {
+        let raw_mode = RawMode::enable()?;
+        if let Some(raw_mode) = &raw_mode {
+            vm.set_interrupt(raw_mode.catch_interrupts());
+        }
+        let stop = vm.resume();
+        drop(raw_mode);
+
+        let action = match stop? {
+            Stop::Halted => return Ok(true),
+            stop => monitor::monitor(vm, stop, &mut io::stdin().lock(), &mut io::stdout())
+                .map_err(|e| e.to_string())?,
+        };
+        match action {
+            Action::Continue => {}
+            Action::Quit => return Ok(false),
+            Action::Halted => return Ok(true),
+        }
     }
 }
 
-fn main() {
-    let matches = Command::new("lc3-vm")
-        .about("An LC-3 virtual machine")
-        .arg(
-            Arg::new("image")
-                .short('i')
-                .long("img")
-                .value_parser(value_parser!(PathBuf))
-                .required(true)
-                .help("The path to the object file"),
-        )
-        .get_matches();
+/// Maps each image to the `--source` it was assembled from, or to its disassembly when no
+/// source matches it.
+fn line_maps(
+    matches: &ArgMatches,
+    images: &[Image],
+    image_files: &[&PathBuf],
+    symbols: &SymbolTable,
+) -> Result<Vec<LineMap>, String> {
+    let mut maps: Vec<Option<LineMap>> = images.iter().map(|_| None).collect();
 
-    let obj_file = matches.get_one::<PathBuf>("image").unwrap();
-    let f = File::open(obj_file).unwrap();
+    for source_file in matches.get_many::<PathBuf>("source").unwrap_or_default() {
+        let source = fs::read_to_string(source_file)
+            .map_err(|e| format!("{}: {}", source_file.display(), e))?;
+        let assembly = assembler::assemble(&source)
+            .map_err(|e| format!("{}: {}", source_file.display(), e))?;
+        let index = images
+            .iter()
+            .position(|image| *image == assembly.image)
+            .ok_or_else(|| {
+                format!(
+                    "{} does not assemble to any of the images",
+                    source_file.display()
+                )
+            })?;
+        maps[index] = Some(LineMap::from_assembly(
+            &source_file.display().to_string(),
+            &source,
+            &assembly,
+        ));
+    }
 
-    let memory = Memory::from_file(f);
+    Ok(maps
+        .into_iter()
+        .zip(images.iter().zip(image_files))
+        .map(|(map, (image, image_file))| {
+            map.unwrap_or_else(|| {
+                LineMap::from_image(&image_file.display().to_string(), image, Some(symbols))
+            })
+        })
+        .collect())
+}
 
-    let registers = Registers::default();
-    let mut vm = Vm::new(registers, memory);
+/// Reads the symbol files given with `--sym`, or the one next to `obj_file` if it exists.
+fn load_symbols(obj_file: &Path, matches: &ArgMatches) -> Result<Option<SymbolTable>, String> {
+    match matches.get_many::<PathBuf>("symbols") {
+        Some(sym_files) => {
+            let mut symbols = SymbolTable::default();
+            for sym_file in sym_files {
+                symbols.merge(&SymbolTable::from_file(sym_file)?);
+            }
+            Ok(Some(symbols))
+        }
+        None => {
+            let sym_file = obj_file.with_extension("sym");
+            sym_file
+                .exists()
+                .then(|| SymbolTable::from_file(&sym_file))
+                .transpose()
+        }
+    }
+}
+
+fn asm(matches: &ArgMatches) -> Result<(), String> {
+    let source_file = matches.get_one::<PathBuf>("source").unwrap();
+    let relocatable = matches.get_flag("relocatable");
+    let obj_file = match matches.get_one::<PathBuf>("output") {
+        Some(path) => path.clone(),
+        None if relocatable => source_file.with_extension("rel"),
+        None => source_file.with_extension("obj"),
+    };
+
+    let source =
+        fs::read_to_string(source_file).map_err(|e| format!("{}: {}", source_file.display(), e))?;
+    let in_source = |e: String| format!("{}: {}", source_file.display(), e);
+
+    if relocatable {
+        let module = assembler::assemble_module(&source).map_err(in_source)?;
+        return module
+            .write(&mut create(&obj_file)?)
+            .map_err(|e| e.to_string());
+    }
 
-    let termios = Termios::from_fd(STDIN).unwrap();
+    let assembly = assembler::assemble(&source).map_err(in_source)?;
+    write_assembly(&assembly, &obj_file)
+}
+
+fn link(matches: &ArgMatches) -> Result<(), String> {
+    let obj_file = matches.get_one::<PathBuf>("output").unwrap();
+    let origin = matches.get_one::<String>("origin").unwrap();
+    let origin = SymbolTable::default().resolve(origin)?;
 
-    let handler = TermiosHandler::new(termios);
-    vm.run();
-    drop(handler);
+    let paths: Vec<&PathBuf> = matches.get_many::<PathBuf>("modules").unwrap().collect();
+    let names: Vec<String> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let mut modules = Vec::new();
+    for (path, name) in paths.iter().zip(&names) {
+        modules.push((name.as_str(), Module::from_file(path)?));
+    }
+
+    let assembly = linker::link(&modules, origin)?;
+    write_assembly(&assembly, obj_file)
+}
+
+/// Writes an assembled or linked image to `obj_file` and its labels next to it.
+fn write_assembly(assembly: &Assembly, obj_file: &Path) -> Result<(), String> {
+    assembly
+        .image
+        .write(&mut create(obj_file)?)
+        .map_err(|e| e.to_string())?;
+    assembly
+        .symbols
+        .write(&mut create(&obj_file.with_extension("sym"))?)
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, String> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn disasm(matches: &ArgMatches) -> Result<(), String> {
+    let obj_file = matches.get_one::<PathBuf>("object").unwrap();
+    let images = formats::read_file(obj_file, matches.get_one::<Format>("format").copied())?;
+
+    let symbols = load_symbols(obj_file, matches)?;
+
+    let no_symbols = SymbolTable::default();
+    let resolve = |arg: &str| {
+        matches
+            .get_one::<String>(arg)
+            .map(|text| symbols.as_ref().unwrap_or(&no_symbols).resolve(text))
+            .transpose()
+    };
+    let from = resolve("from")?.unwrap_or(0);
+    let to = resolve("to")?.unwrap_or(u16::MAX);
+
+    let mut out = io::stdout().lock();
+    for image in &images {
+        disasm::write_listing(&mut out, &image.range(from, to), symbols.as_ref())
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn objinfo(matches: &ArgMatches) -> Result<(), String> {
+    let obj_file = matches.get_one::<PathBuf>("object").unwrap();
+    let bytes = fs::read(obj_file).map_err(|e| format!("{}: {}", obj_file.display(), e))?;
+    let format = matches
+        .get_one::<Format>("format")
+        .copied()
+        .unwrap_or_else(|| Format::of_file(obj_file, &bytes));
+
+    // An odd trailing byte keeps the image from loading, but the words before it can
+    // still be inspected.
+    let odd_byte = format == Format::Obj && bytes.len() % 2 == 1;
+    let whole_words = if odd_byte {
+        &bytes[..bytes.len() - 1]
+    } else {
+        &bytes[..]
+    };
+    let images =
+        formats::read(whole_words, format).map_err(|e| format!("{}: {}", obj_file.display(), e))?;
+
+    println!("file          {}", obj_file.display());
+    println!("format        {}", format);
+    if odd_byte {
+        println!(
+            "warning       odd trailing byte x{:02X}, so the image does not load",
+            bytes[bytes.len() - 1]
+        );
+    }
+    for image in &images {
+        println!();
+        print!("{}", objinfo::inspect(image));
+    }
+
+    Ok(())
+}
+
+fn convert(matches: &ArgMatches) -> Result<(), String> {
+    let format = matches.get_one::<Format>("format").copied();
+    let mut images = Vec::new();
+    for input in matches.get_many::<PathBuf>("input").unwrap() {
+        images.extend(formats::read_file(input, format)?);
+    }
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let output_format = matches
+        .get_one::<Format>("output_format")
+        .copied()
+        .or_else(|| Format::from_extension(output))
+        .unwrap_or(Format::Obj);
+
+    let from = matches.get_one::<String>("from");
+    let to = matches.get_one::<String>("to");
+    if from.is_some() || to.is_some() {
+        let mut memory = Memory::default();
+        loader::load(&mut memory, &images);
+
+        let symbols = SymbolTable::default();
+        let start = match from {
+            Some(from) => symbols.resolve(from)?,
+            None => images.iter().map(|image| image.origin).min().unwrap_or(0),
+        };
+        let end = match to {
+            Some(to) => symbols.resolve(to)?,
+            None => images.iter().filter_map(Image::end).max().unwrap_or(start),
+        };
+        if end < start {
+            return Err(format!("x{:04X} is before x{:04X}", end, start));
+        }
+
+        images = vec![Image {
+            origin: start,
+            words: (start..=end).map(|addr| memory.peek(addr)).collect(),
+        }];
+    }
+
+    let file = File::create(output).map_err(|e| format!("{}: {}", output.display(), e))?;
+    formats::write(&mut BufWriter::new(file), &images, output_format)
+}
+
+fn test(matches: &ArgMatches) -> Result<(), String> {
+    let spec_file = matches.get_one::<PathBuf>("spec").unwrap();
+    let results = Spec::from_file(spec_file)?.run();
+
+    spec::write_report(&mut io::stdout().lock(), &results).map_err(|e| e.to_string())?;
+    if let Some(json) = matches.get_one::<PathBuf>("json") {
+        fs::write(json, format!("{:#}\n", spec::to_json(&results)))
+            .map_err(|e| format!("{}: {}", json.display(), e))?;
+    }
+    if let Some(junit) = matches.get_one::<PathBuf>("junit") {
+        let suite = spec_file.file_stem().unwrap_or_default().to_string_lossy();
+        spec::write_junit(&mut create(junit)?, &suite, &results)
+            .map_err(|e| format!("{}: {}", junit.display(), e))?;
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.outcome != Outcome::Passed)
+        .count();
+    if failed > 0 {
+        return Err(format!("{} of {} cases failed", failed, results.len()));
+    }
+
+    Ok(())
+}
+
+fn batch(matches: &ArgMatches) -> Result<(), String> {
+    let spec = Spec::from_file(matches.get_one::<PathBuf>("spec").unwrap())?;
+    let submissions: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("submissions")
+        .unwrap()
+        .cloned()
+        .collect();
+    let jobs = match matches.get_one::<usize>("jobs") {
+        Some(&jobs) => jobs,
+        None => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+    };
+    let timeout = Duration::try_from_secs_f64(*matches.get_one::<f64>("timeout").unwrap())
+        .map_err(|e| format!("invalid timeout: {}", e))?;
+
+    let submissions = batch::run(&spec, &submissions, jobs, Some(timeout));
+
+    batch::write_summary(&mut io::stdout().lock(), &submissions).map_err(|e| e.to_string())?;
+    if let Some(dir) = matches.get_one::<PathBuf>("results") {
+        batch::write_results(dir, &submissions)?;
+    }
 
-    println!("execution finished ok");
+    Ok(())
 }
@@ -1,42 +1,278 @@
-use std::{fs::File, io::BufReader};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::fmt;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use crate::{console::Console, registers::MemoryMappedReg, utils::handle_keyboard};
+#[cfg(feature = "alloc")]
+use crate::{image::Image, instructions::Instruction};
 
-use crate::{registers::MemoryMappedReg, utils::handle_keyboard};
+/// The console programs use unless given another: the host terminal when there is one.
+#[cfg(feature = "terminal")]
+type DefaultConsole = crate::terminal::Terminal;
+#[cfg(not(feature = "terminal"))]
+type DefaultConsole = crate::console::Disconnected;
 
 pub const MEMORY_SIZE: usize = 0x10000;
+/// Last address of the system area: trap and interrupt vector tables and the OS.
+pub const SYSTEM_SPACE_END: u16 = 0x2FFF;
+/// First address of the memory-mapped device registers.
+pub const DEVICE_SPACE_START: u16 = 0xFE00;
+
+/// The areas of the LC-3 memory map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    TrapVectors,
+    InterruptVectors,
+    System,
+    User,
+    Devices,
+}
+
+impl Region {
+    pub const ALL: [Region; 5] = [
+        Region::TrapVectors,
+        Region::InterruptVectors,
+        Region::System,
+        Region::User,
+        Region::Devices,
+    ];
+
+    pub fn of(addr: u16) -> Self {
+        match addr {
+            0x0000..=0x00FF => Region::TrapVectors,
+            0x0100..=0x01FF => Region::InterruptVectors,
+            0x0200..=0x2FFF => Region::System,
+            0x3000..=0xFDFF => Region::User,
+            0xFE00..=0xFFFF => Region::Devices,
+        }
+    }
+
+    /// The first and last address of the region.
+    pub fn bounds(self) -> (u16, u16) {
+        match self {
+            Region::TrapVectors => (0x0000, 0x00FF),
+            Region::InterruptVectors => (0x0100, 0x01FF),
+            Region::System => (0x0200, 0x2FFF),
+            Region::User => (0x3000, 0xFDFF),
+            Region::Devices => (0xFE00, 0xFFFF),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Region::TrapVectors => write!(f, "trap vectors"),
+            Region::InterruptVectors => write!(f, "interrupt vectors"),
+            Region::System => write!(f, "system"),
+            Region::User => write!(f, "user"),
+            Region::Devices => write!(f, "devices"),
+        }
+    }
+}
+
+/// Data reads and writes per [`Region`], not counting instruction fetches.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccessCounts {
+    reads: [u64; Region::ALL.len()],
+    writes: [u64; Region::ALL.len()],
+}
+
+impl AccessCounts {
+    pub fn reads(&self, region: Region) -> u64 {
+        self.reads[region as usize]
+    }
+
+    pub fn writes(&self, region: Region) -> u64 {
+        self.writes[region as usize]
+    }
+}
+
+/// Where [`Memory::console`] reads and writes: the default console, or one set by the host,
+/// boxed or, without an allocator, in a static.
+enum Attached {
+    Default(DefaultConsole),
+    Static(&'static mut dyn Console),
+    #[cfg(feature = "alloc")]
+    Boxed(Box<dyn Console>),
+}
 
 pub struct Memory {
     memory: [u16; MEMORY_SIZE],
+    accesses: Option<AccessCounts>,
+    #[cfg(feature = "alloc")]
+    predecode: bool,
+    /// Instructions already decoded, by address, dropped when their address is written.
+    /// Empty until the first [`Memory::decode`] with predecoding on.
+    #[cfg(feature = "alloc")]
+    decoded: Vec<Option<Instruction>>,
+    /// Addresses holding translated code, see [`Memory::code_generation`]. Empty until the
+    /// first [`Memory::mark_code`].
+    #[cfg(feature = "alloc")]
+    code: Vec<bool>,
+    /// The addresses set in `code`, so that clearing them costs no more than marking them.
+    #[cfg(feature = "alloc")]
+    code_addrs: Vec<u16>,
+    #[cfg(feature = "alloc")]
+    code_generation: u64,
+    console: Attached,
+    input_ended: bool,
+    /// The origin of the first image loaded, where execution starts by default.
+    #[cfg(feature = "alloc")]
+    entry: Option<u16>,
 }
 
 impl Memory {
     pub fn read(&mut self, addr: u16) -> u16 {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.reads[Region::of(addr) as usize] += 1;
+        }
+
+        self.fetch(addr)
+    }
+
+    /// Reads an instruction word, which is not counted as a data read.
+    pub fn fetch(&mut self, addr: u16) -> u16 {
         if addr == MemoryMappedReg::Kbsr as u16 {
             handle_keyboard(self);
         }
         self.memory[addr as usize]
     }
 
+    /// Reads a word without the side effects of reading a device register.
+    pub fn peek(&self, addr: u16) -> u16 {
+        self.memory[addr as usize]
+    }
+
     pub fn write(&mut self, addr: u16, data: u16) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.writes[Region::of(addr) as usize] += 1;
+        }
+        #[cfg(feature = "alloc")]
+        {
+            if let Some(decoded) = self.decoded.get_mut(addr as usize) {
+                *decoded = None;
+            }
+            if self.code.get(addr as usize) == Some(&true) {
+                self.code_generation += 1;
+            }
+        }
+
         self.memory[addr as usize] = data;
     }
 
-    pub fn from_file(file: File) -> Self {
-        let mut file: BufReader<File> = BufReader::new(file);
+    #[cfg(feature = "alloc")]
+    pub fn load(&mut self, image: &Image) {
+        self.entry.get_or_insert(image.origin);
+        for (index, &bits) in image.words.iter().enumerate() {
+            self.write(image.address(index), bits);
+        }
+    }
 
-        let mut memory = Memory::default();
+    /// The origin of the first image loaded, if any.
+    #[cfg(feature = "alloc")]
+    pub fn entry(&self) -> Option<u16> {
+        self.entry
+    }
+
+    /// Decodes the instruction at `addr`, without the side effects of reading a device
+    /// register. Unless turned off with [`Memory::set_predecode`], the decoded instruction is
+    /// kept and reused until `addr` is written to.
+    #[cfg(feature = "alloc")]
+    pub fn decode(&mut self, addr: u16) -> Result<Instruction, String> {
+        let bits = self.memory[addr as usize];
+        if !self.predecode {
+            return Instruction::try_from(bits);
+        }
+        if self.decoded.is_empty() {
+            self.decoded = vec![None; MEMORY_SIZE];
+        }
+
+        if let Some(instruction) = self.decoded[addr as usize] {
+            return Ok(instruction);
+        }
+        let instruction = Instruction::try_from(bits)?;
+        self.decoded[addr as usize] = Some(instruction);
+
+        Ok(instruction)
+    }
+
+    /// Turns keeping decoded instructions on, the default, or off.
+    #[cfg(feature = "alloc")]
+    pub fn set_predecode(&mut self, enabled: bool) {
+        self.predecode = enabled;
+        self.decoded = Vec::new();
+    }
+
+    /// Marks `addr` as holding translated code, so that writing to it changes the
+    /// [code generation](Memory::code_generation).
+    #[cfg(feature = "alloc")]
+    pub fn mark_code(&mut self, addr: u16) {
+        if self.code.is_empty() {
+            self.code = vec![false; MEMORY_SIZE];
+        }
+        if !self.code[addr as usize] {
+            self.code[addr as usize] = true;
+            self.code_addrs.push(addr);
+        }
+    }
+
+    /// A number that changes whenever an address marked with [`Memory::mark_code`] is
+    /// written, telling translated code apart from what memory now holds.
+    #[cfg(feature = "alloc")]
+    pub fn code_generation(&self) -> u64 {
+        self.code_generation
+    }
+
+    /// Forgets every [`Memory::mark_code`] mark, once the translated code is dropped.
+    #[cfg(feature = "alloc")]
+    pub fn clear_code_marks(&mut self) {
+        for addr in self.code_addrs.drain(..) {
+            self.code[addr as usize] = false;
+        }
+    }
 
-        let mut addr = file
-            .read_u16::<BigEndian>()
-            .expect("error reading base address");
+    /// Replaces the terminal as the source of keys and the destination of output.
+    #[cfg(feature = "alloc")]
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.console = Attached::Boxed(console);
+    }
+
+    /// Like [`Memory::set_console`], for a console that lives in a static, as hosts without
+    /// an allocator keep it.
+    pub fn set_static_console(&mut self, console: &'static mut dyn Console) {
+        self.console = Attached::Static(console);
+    }
 
-        while let Ok(bits) = file.read_u16::<BigEndian>() {
-            memory.write(addr, bits);
-            addr = addr.checked_add(1).expect("file too large");
+    pub fn console(&mut self) -> &mut dyn Console {
+        match &mut self.console {
+            Attached::Default(console) => console,
+            Attached::Static(console) => *console,
+            #[cfg(feature = "alloc")]
+            Attached::Boxed(console) => console.as_mut(),
         }
+    }
+
+    /// Waits for the next key from the console, see [`Console::read_key`].
+    pub fn read_key(&mut self) -> Option<u8> {
+        let key = self.console().read_key();
+        self.input_ended |= key.is_none();
+
+        key
+    }
+
+    /// Whether a program asked for a key after the console ran out of input.
+    pub fn input_ended(&self) -> bool {
+        self.input_ended
+    }
 
-        memory
+    /// Starts counting reads and writes per region.
+    pub fn count_accesses(&mut self) {
+        self.accesses.get_or_insert_with(AccessCounts::default);
+    }
+
+    pub fn accesses(&self) -> Option<&AccessCounts> {
+        self.accesses.as_ref()
     }
 }
 
@@ -44,12 +280,30 @@ impl Default for Memory {
     fn default() -> Self {
         Self {
             memory: [0; MEMORY_SIZE],
+            accesses: None,
+            #[cfg(feature = "alloc")]
+            predecode: true,
+            #[cfg(feature = "alloc")]
+            decoded: Vec::new(),
+            #[cfg(feature = "alloc")]
+            code: Vec::new(),
+            #[cfg(feature = "alloc")]
+            code_addrs: Vec::new(),
+            #[cfg(feature = "alloc")]
+            code_generation: 0,
+            console: Attached::Default(DefaultConsole::default()),
+            input_ended: false,
+            #[cfg(feature = "alloc")]
+            entry: None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::boxed::Box;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
     use super::*;
 
     #[test]
@@ -77,6 +331,100 @@ mod tests {
         assert_eq!(memory.read(0x3000), 0xABCD);
     }
 
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_load_image() {
+        let mut memory = Memory::default();
+
+        memory.load(&Image {
+            origin: 0x4000,
+            words: vec![0x1234, 0x5678],
+        });
+
+        assert_eq!(memory.read(0x4000), 0x1234);
+        assert_eq!(memory.read(0x4001), 0x5678);
+    }
+
+    #[test]
+    fn test_count_accesses() {
+        let mut memory = Memory::default();
+        memory.count_accesses();
+
+        memory.write(0x3000, 1);
+        memory.read(0x3000);
+        memory.read(0x0025);
+        memory.fetch(0x3000);
+
+        let accesses = memory.accesses().unwrap();
+        assert_eq!(accesses.reads(Region::User), 1);
+        assert_eq!(accesses.writes(Region::User), 1);
+        assert_eq!(accesses.reads(Region::TrapVectors), 1);
+        assert_eq!(accesses.reads(Region::Devices), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_decode_sees_writes() {
+        let mut memory = Memory::default();
+        // ADD  R0, R0, #1
+        memory.write(0x3000, 0b0001_000_000_1_00001);
+        let add = memory.decode(0x3000).unwrap();
+        assert_eq!(memory.decode(0x3000), Ok(add));
+
+        // HALT
+        memory.write(0x3000, 0xF025);
+        assert!(memory.decode(0x3000).unwrap().is_halt());
+
+        memory.write(0x3000, 0xD000);
+        assert!(memory.decode(0x3000).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_caches_allocated_on_use() {
+        let mut memory = Memory::default();
+        // ADD  R0, R0, #1
+        memory.write(0x3000, 0b0001_000_000_1_00001);
+        assert!(memory.decoded.is_empty());
+        assert!(memory.code.is_empty());
+
+        memory.decode(0x3000).unwrap();
+        memory.mark_code(0x3000);
+        assert_eq!(memory.decoded.len(), MEMORY_SIZE);
+        assert_eq!(memory.code.len(), MEMORY_SIZE);
+
+        memory.write(0x3000, 0xF025);
+        assert!(memory.decode(0x3000).unwrap().is_halt());
+        assert_eq!(memory.code_generation(), 1);
+    }
+
+    #[test]
+    fn test_static_console() {
+        static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counter;
+
+        impl Console for Counter {
+            fn read_key(&mut self) -> Option<u8> {
+                Some(b'k')
+            }
+
+            fn write(&mut self, text: &str) {
+                WRITTEN.fetch_add(text.len(), Ordering::SeqCst);
+            }
+
+            fn flush(&mut self) {}
+        }
+
+        let mut memory = Memory::default();
+        memory.set_static_console(Box::leak(Box::new(Counter)));
+
+        assert_eq!(memory.read(MemoryMappedReg::Kbsr as u16), 0x8000);
+        assert_eq!(memory.read(MemoryMappedReg::Kbdr as u16), b'k' as u16);
+        memory.console().write("hi");
+        assert_eq!(WRITTEN.load(Ordering::SeqCst), 2);
+    }
+
     #[test]
     fn test_index_last_memory_block() {
         let mut memory = Memory::default();
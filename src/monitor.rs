@@ -0,0 +1,283 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    instructions::Instruction,
+    registers::{CondFlag, Register},
+    symbols::{format_address, SymbolTable},
+    vm::{Stop, Vm},
+};
+
+/// Instructions listed before and after the PC.
+const CONTEXT_BEFORE: u16 = 2;
+const CONTEXT_AFTER: u16 = 4;
+/// Words shown by `mem` when no count is given, and per line.
+const MEMORY_WORDS: u16 = 16;
+const WORDS_PER_LINE: u16 = 8;
+
+const HELP: &str = "\
+c, continue        run until the next breakpoint, interrupt or HALT
+s, step [n]        execute n instructions (1)
+b, break [addr]    set a breakpoint at addr, or list breakpoints
+d, delete <addr>   remove the breakpoint at addr
+r, regs            show registers and the code around the PC
+l, list [addr]     disassemble around addr (the PC)
+m, mem <addr> [n]  show n words of memory from addr (16)
+q, quit            stop the program
+";
+
+/// What the monitor left the program to do.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    Quit,
+    /// Stepping reached HALT.
+    Halted,
+}
+
+/// Shows why and where `vm` stopped, then reads commands from `input` until told to
+/// continue or quit. Addresses can be numbers or labels from the VM's symbols.
+pub fn monitor(
+    vm: &mut Vm,
+    stop: Stop,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<Action> {
+    let pc = vm.registers().get(Register::PC);
+    writeln!(out, "{} at {}", stop, format_address(vm.symbols(), pc))?;
+    write_state(out, vm)?;
+
+    loop {
+        write!(out, "(lc3) ")?;
+        out.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(Action::Quit);
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words[..] {
+            [] => {}
+            ["c" | "continue"] => return Ok(Action::Continue),
+            ["q" | "quit"] => return Ok(Action::Quit),
+            ["h" | "help" | "?"] => write!(out, "{}", HELP)?,
+            ["r" | "regs"] => write_state(out, vm)?,
+            ["s" | "step", ..] => {
+                let count = match words.get(1).map(|n| n.parse::<u64>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        writeln!(out, "expected a number of instructions")?;
+                        continue;
+                    }
+                };
+                for _ in 0..count {
+                    match vm.step() {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            writeln!(out, "halted")?;
+                            return Ok(Action::Halted);
+                        }
+                        Err(e) => {
+                            writeln!(out, "error: {}", e)?;
+                            break;
+                        }
+                    }
+                }
+                write_state(out, vm)?;
+            }
+            ["b" | "break"] => {
+                for addr in vm.breakpoints() {
+                    writeln!(out, "{}", format_address(vm.symbols(), addr))?;
+                }
+            }
+            ["b" | "break", addr] => match resolve(vm, addr) {
+                Ok(addr) => vm.add_breakpoint(addr),
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            ["d" | "delete", addr] => match resolve(vm, addr) {
+                Ok(addr) if vm.remove_breakpoint(addr) => {}
+                Ok(addr) => writeln!(out, "no breakpoint at x{:04X}", addr)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            ["l" | "list"] => write_listing(out, vm, pc_of(vm))?,
+            ["l" | "list", addr] => match resolve(vm, addr) {
+                Ok(addr) => write_listing(out, vm, addr)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            ["m" | "mem", addr] => match resolve(vm, addr) {
+                Ok(addr) => write_memory(out, vm, addr, MEMORY_WORDS)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            ["m" | "mem", addr, count] => match (resolve(vm, addr), count.parse::<u16>()) {
+                (Ok(addr), Ok(count)) => write_memory(out, vm, addr, count)?,
+                (Err(e), _) => writeln!(out, "{}", e)?,
+                (_, Err(_)) => writeln!(out, "expected a number of words")?,
+            },
+            _ => writeln!(out, "unknown command '{}', try help", line.trim())?,
+        }
+    }
+}
+
+fn pc_of(vm: &Vm) -> u16 {
+    vm.registers().get(Register::PC)
+}
+
+fn resolve(vm: &Vm, text: &str) -> Result<u16, String> {
+    match vm.symbols() {
+        Some(symbols) => symbols.resolve(text),
+        None => SymbolTable::default().resolve(text),
+    }
+}
+
+/// Registers, condition codes and the code around the PC.
+fn write_state(out: &mut impl Write, vm: &Vm) -> io::Result<()> {
+    let registers = vm.registers();
+    let general = [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+    ];
+    let values: Vec<String> = general
+        .iter()
+        .map(|&register| format!("{} x{:04X}", register, registers.get(register)))
+        .collect();
+    writeln!(out, "{}", values.join("  "))?;
+
+    let cond = registers.get(Register::COND);
+    let flag = |set: CondFlag, c: char| if cond == set as u16 { c } else { '-' };
+    writeln!(
+        out,
+        "PC x{:04X}  PSR x{:04X}  CC {}{}{}",
+        registers.get(Register::PC),
+        registers.psr(),
+        flag(CondFlag::NEG, 'n'),
+        flag(CondFlag::ZRO, 'z'),
+        flag(CondFlag::POS, 'p'),
+    )?;
+
+    write_listing(out, vm, pc_of(vm))
+}
+
+/// Disassembles the words around `addr`, marking the PC with `>` and breakpoints with `*`.
+fn write_listing(out: &mut impl Write, vm: &Vm, addr: u16) -> io::Result<()> {
+    let pc = pc_of(vm);
+    let breakpoints: Vec<u16> = vm.breakpoints().collect();
+    let symbols = vm.symbols();
+
+    for offset in 0..=CONTEXT_BEFORE + CONTEXT_AFTER {
+        let addr = addr.wrapping_sub(CONTEXT_BEFORE).wrapping_add(offset);
+        let bits = vm.memory().peek(addr);
+        let text = match Instruction::try_from(bits) {
+            Ok(instruction) => instruction.at(addr, symbols).to_string(),
+            Err(_) => format!(".FILL x{:04X}", bits),
+        };
+
+        writeln!(
+            out,
+            "{}{} {:<12} x{:04X}  {}",
+            if breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            },
+            if addr == pc { '>' } else { ' ' },
+            format_address(symbols, addr),
+            bits,
+            text
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_memory(out: &mut impl Write, vm: &Vm, addr: u16, count: u16) -> io::Result<()> {
+    for line in (0..count).step_by(WORDS_PER_LINE as usize) {
+        let start = addr.wrapping_add(line);
+        let words: Vec<String> = (line..count.min(line + WORDS_PER_LINE))
+            .map(|offset| format!("x{:04X}", vm.memory().peek(addr.wrapping_add(offset))))
+            .collect();
+        writeln!(out, "x{:04X}  {}", start, words.join(" "))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, registers::Registers};
+
+    fn vm() -> Vm {
+        let assembly = assemble(
+            "
+            .ORIG x3000
+            AND R0, R0, #0
+            LOOP ADD R0, R0, #1
+            BRnzp LOOP
+            .END
+            ",
+        )
+        .unwrap();
+        let mut memory = Memory::default();
+        memory.load(&assembly.image);
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_symbols(assembly.symbols);
+        vm
+    }
+
+    fn run_monitor(vm: &mut Vm, stop: Stop, commands: &str) -> (Action, String) {
+        let mut out = Vec::new();
+        let action = monitor(vm, stop, &mut commands.as_bytes(), &mut out).unwrap();
+
+        (action, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_monitor_shows_state_and_steps() {
+        let mut vm = vm();
+
+        let (action, out) = run_monitor(&mut vm, Stop::Interrupted, "s 3\nc\n");
+
+        assert_eq!(action, Action::Continue);
+        assert!(out.starts_with("interrupted at x3000\nR0 x0000  R1 x0000"));
+        assert!(out.contains(" > x3000        x5020  AND R0, R0, #0\n"));
+        assert!(out.contains(" > LOOP         x1021  ADD R0, R0, #1\n"));
+        assert!(out.contains("R0 x0001"));
+        assert!(out.contains("CC --p"));
+        assert_eq!(vm.registers().get(Register::R0), 1);
+    }
+
+    #[test]
+    fn test_monitor_breakpoints() {
+        let mut vm = vm();
+
+        let (action, out) = run_monitor(&mut vm, Stop::Interrupted, "b LOOP+1\nb\nb NOPE\n");
+        assert_eq!(action, Action::Quit);
+        assert!(out.contains("LOOP+1\n"));
+        assert!(out.contains("unknown label 'NOPE'"));
+
+        assert_eq!(vm.resume(), Ok(Stop::Breakpoint(0x3002)));
+        assert_eq!(vm.resume(), Ok(Stop::Breakpoint(0x3002)));
+        assert_eq!(vm.registers().get(Register::R0), 2);
+
+        let (action, out) = run_monitor(&mut vm, Stop::Breakpoint(0x3002), "d LOOP+1\nq\n");
+        assert_eq!(action, Action::Quit);
+        assert!(out.starts_with("breakpoint at LOOP+1\n"));
+        assert_eq!(vm.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn test_monitor_memory() {
+        let mut vm = vm();
+
+        let (_, out) = run_monitor(&mut vm, Stop::Interrupted, "m x3000 3\n");
+
+        assert!(out.contains("x3000  x5020 x1021 x0FFE\n"));
+    }
+}
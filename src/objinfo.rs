@@ -0,0 +1,182 @@
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    image::Image,
+    instructions::Instruction,
+    memory::{DEVICE_SPACE_START, SYSTEM_SPACE_END},
+    opcodes::Opcode,
+};
+
+/// What an image contains, found without running it.
+#[derive(Debug, PartialEq)]
+pub struct ObjInfo {
+    pub origin: u16,
+    pub len: usize,
+    pub end: Option<u16>,
+    pub in_system_space: bool,
+    pub in_device_space: bool,
+    /// Words that decode as instructions and are reachable from the origin.
+    pub code: usize,
+    /// Words never reached from the origin, including those that are not instructions.
+    pub data: usize,
+    /// Data words that would not decode as an instruction either.
+    pub undecodable: usize,
+    /// Number of reachable instructions per opcode.
+    pub opcodes: BTreeMap<String, usize>,
+}
+
+/// Inspects `image`, following every statically known branch, call and fall-through from
+/// the origin to tell code apart from data.
+pub fn inspect(image: &Image) -> ObjInfo {
+    let reachable = reachable(image);
+
+    let mut opcodes = BTreeMap::new();
+    let mut undecodable = 0;
+    for (&bits, &reachable) in image.words.iter().zip(&reachable) {
+        if reachable {
+            *opcodes
+                .entry(format!("{:?}", Opcode::from(bits)))
+                .or_insert(0) += 1;
+        } else if Instruction::try_from(bits).is_err() {
+            undecodable += 1;
+        }
+    }
+
+    let code = reachable.iter().filter(|&&reachable| reachable).count();
+    let end = image.end();
+
+    ObjInfo {
+        origin: image.origin,
+        len: image.words.len(),
+        end,
+        in_system_space: end.is_some() && image.origin <= SYSTEM_SPACE_END,
+        in_device_space: end.is_some_and(|end| end >= DEVICE_SPACE_START),
+        code,
+        data: image.words.len() - code,
+        undecodable,
+        opcodes,
+    }
+}
+
+/// Which words of `image` are instructions reachable from its origin through fall-through,
+/// branches and calls with statically known targets.
+pub fn reachable(image: &Image) -> Vec<bool> {
+    let index_of = |addr: u16| {
+        let index = addr.wrapping_sub(image.origin) as usize;
+        (index < image.words.len()).then_some(index)
+    };
+
+    let mut reachable = vec![false; image.words.len()];
+    let mut pending = vec![image.origin];
+    while let Some(addr) = pending.pop() {
+        let Some(index) = index_of(addr) else {
+            continue;
+        };
+        if reachable[index] {
+            continue;
+        }
+        let Ok(instruction) = Instruction::try_from(image.words[index]) else {
+            continue;
+        };
+
+        reachable[index] = true;
+        pending.extend(instruction.successors(addr));
+    }
+
+    reachable
+}
+
+impl fmt::Display for ObjInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        writeln!(f, "origin        x{:04X}", self.origin)?;
+        match self.end {
+            Some(end) => writeln!(f, "end           x{:04X}", end)?,
+            None => writeln!(f, "end           -")?,
+        }
+        writeln!(f, "length        {} words", self.len)?;
+        writeln!(
+            f,
+            "system area   {} (x0000-x{:04X})",
+            yes_no(self.in_system_space),
+            SYSTEM_SPACE_END
+        )?;
+        writeln!(
+            f,
+            "device area   {} (x{:04X}-xFFFF)",
+            yes_no(self.in_device_space),
+            DEVICE_SPACE_START
+        )?;
+        writeln!(
+            f,
+            "code          {} words reachable from the origin",
+            self.code
+        )?;
+        writeln!(
+            f,
+            "data          {} words, {} of them not valid instructions",
+            self.data, self.undecodable
+        )?;
+
+        let mut opcodes: Vec<(&String, &usize)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (opcode, count) in opcodes {
+            writeln!(f, "  {:<6} {:>6}", opcode, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_inspect_separates_code_from_data() {
+        let source = "
+                .ORIG x3000
+                LD R1, COUNT
+        LOOP    ADD R1, R1, #-1
+                BRp LOOP
+                JSR SUB
+                HALT
+        COUNT   .FILL xD000
+        SUB     RET
+        UNUSED  ADD R0, R0, R0
+                .END
+        ";
+        let image = assemble(source).unwrap().image;
+
+        let info = inspect(&image);
+
+        assert_eq!(info.origin, 0x3000);
+        assert_eq!(info.end, Some(0x3007));
+        assert_eq!(info.len, 8);
+        assert_eq!(info.code, 6);
+        assert_eq!(info.data, 2);
+        assert_eq!(info.undecodable, 1);
+        assert_eq!(info.opcodes.get("ADD"), Some(&1));
+        assert_eq!(info.opcodes.get("JMP"), Some(&1));
+        assert!(!info.in_system_space);
+        assert!(!info.in_device_space);
+    }
+
+    #[test]
+    fn test_inspect_flags_reserved_areas() {
+        let image = Image {
+            origin: 0x2FFF,
+            words: vec![0xF025],
+        };
+        assert!(inspect(&image).in_system_space);
+
+        let image = Image {
+            origin: 0xFDFF,
+            words: vec![0, 0],
+        };
+        assert!(inspect(&image).in_device_space);
+    }
+}
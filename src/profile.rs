@@ -0,0 +1,483 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    instructions::Instruction,
+    memory::{Memory, Region, MEMORY_SIZE},
+    opcodes::Opcode,
+    symbols::{format_address, SymbolTable},
+};
+
+/// How many of the most executed addresses the report lists.
+const HOT_SPOTS: usize = 20;
+
+/// Instructions executed on behalf of one subroutine, keyed by its entry address.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// Instructions from entering the subroutine until it returns, callees included.
+    pub inclusive: u64,
+    /// Instructions of the subroutine itself, callees excluded.
+    pub exclusive: u64,
+}
+
+/// A call to the subroutine at `entry`, `depth` calls deep, that ran for `duration`
+/// instructions from the `start`-th instruction executed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub entry: u16,
+    pub depth: usize,
+    pub start: u64,
+    pub duration: u64,
+}
+
+#[derive(Debug)]
+struct Frame {
+    entry: u16,
+    return_addr: u16,
+    start: u64,
+    node: usize,
+}
+
+/// One path of calls from where the program started, with the instructions executed while
+/// it was the current call stack.
+#[derive(Debug)]
+struct CallNode {
+    entry: u16,
+    parent: Option<usize>,
+    children: BTreeMap<u16, usize>,
+    instructions: u64,
+}
+
+/// Execution counts gathered while the VM runs.
+///
+/// Time is measured in executed instructions, which unlike wall-clock time does not depend
+/// on the host or on how long the program waits for keyboard input.
+#[derive(Debug)]
+pub struct Profile {
+    instructions: u64,
+    executions: Vec<u64>,
+    opcodes: [u64; 16],
+    stack: Vec<Frame>,
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    calls: Vec<CallNode>,
+    spans: Option<Vec<Span>>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            instructions: 0,
+            executions: vec![0; MEMORY_SIZE],
+            opcodes: [0; 16],
+            stack: Vec::new(),
+            subroutines: BTreeMap::new(),
+            calls: vec![CallNode {
+                entry: 0,
+                parent: None,
+                children: BTreeMap::new(),
+                instructions: 0,
+            }],
+            spans: None,
+        }
+    }
+}
+
+impl Profile {
+    /// A profile that also keeps every call as a [`Span`], which costs memory for each call
+    /// the program makes.
+    pub fn with_spans() -> Self {
+        Self {
+            spans: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Counts the instruction `bits` fetched from `pc`.
+    pub fn record_execution(&mut self, pc: u16, bits: u16) {
+        if self.instructions == 0 {
+            self.calls[0].entry = pc;
+        }
+
+        self.instructions += 1;
+        self.executions[pc as usize] += 1;
+        self.opcodes[(bits >> 12) as usize] += 1;
+        let call = self.current_call();
+        self.calls[call].instructions += 1;
+
+        if let Some(frame) = self.stack.last() {
+            self.subroutines.entry(frame.entry).or_default().exclusive += 1;
+        }
+    }
+
+    fn current_call(&self) -> usize {
+        self.stack.last().map_or(0, |frame| frame.node)
+    }
+
+    /// Tracks calls and returns once `instruction`, fetched from `pc`, has moved the PC to
+    /// `next_pc`.
+    ///
+    /// A `RET` closes the innermost call that would return to `next_pc`, along with any calls
+    /// made from it that never returned; one that matches no call is ignored.
+    pub fn record_control_flow(&mut self, pc: u16, instruction: &Instruction, next_pc: u16) {
+        if instruction.is_call() {
+            self.subroutines.entry(next_pc).or_default().calls += 1;
+
+            let parent = self.current_call();
+            let node = match self.calls[parent].children.get(&next_pc) {
+                Some(&node) => node,
+                None => {
+                    self.calls.push(CallNode {
+                        entry: next_pc,
+                        parent: Some(parent),
+                        children: BTreeMap::new(),
+                        instructions: 0,
+                    });
+                    let node = self.calls.len() - 1;
+                    self.calls[parent].children.insert(next_pc, node);
+                    node
+                }
+            };
+
+            self.stack.push(Frame {
+                entry: next_pc,
+                return_addr: pc.wrapping_add(1),
+                start: self.instructions,
+                node,
+            });
+        } else if instruction.is_return() {
+            let Some(depth) = self
+                .stack
+                .iter()
+                .rposition(|frame| frame.return_addr == next_pc)
+            else {
+                return;
+            };
+            for (index, frame) in self.stack.drain(depth..).enumerate().rev() {
+                let duration = self.instructions - frame.start;
+                self.subroutines.entry(frame.entry).or_default().inclusive += duration;
+                if let Some(spans) = &mut self.spans {
+                    spans.push(Span {
+                        entry: frame.entry,
+                        depth: depth + index + 1,
+                        start: frame.start,
+                        duration,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions[addr as usize]
+    }
+
+    /// Every executed address and its count, most executed first.
+    pub fn hot_spots(&self) -> Vec<(u16, u64)> {
+        let mut hot_spots: Vec<(u16, u64)> = (0..=u16::MAX)
+            .map(|addr| (addr, self.executions(addr)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hot_spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        hot_spots
+    }
+
+    /// Every executed opcode and its count, most executed first.
+    pub fn opcodes(&self) -> Vec<(Opcode, u64)> {
+        let mut opcodes: Vec<(Opcode, u64)> = (0..16)
+            .map(|index| (Opcode::from(index << 12), self.opcodes[index as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by_key(|&(_, count)| Reverse(count));
+
+        opcodes
+    }
+
+    /// Every called subroutine, most inclusive instructions first. Calls that have not
+    /// returned yet count up to now.
+    pub fn subroutines(&self) -> Vec<(u16, SubroutineStats)> {
+        let mut subroutines = self.subroutines.clone();
+        for frame in &self.stack {
+            subroutines.entry(frame.entry).or_default().inclusive +=
+                self.instructions - frame.start;
+        }
+
+        let mut subroutines: Vec<(u16, SubroutineStats)> = subroutines.into_iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+
+        subroutines
+    }
+
+    /// Every call stack the program executed instructions in, outermost subroutine first and
+    /// starting with the address execution began at, with how many instructions it executed.
+    pub fn stacks(&self) -> Vec<(Vec<u16>, u64)> {
+        let mut stacks = Vec::new();
+
+        for (index, call) in self.calls.iter().enumerate() {
+            if call.instructions == 0 {
+                continue;
+            }
+
+            let mut stack = Vec::new();
+            let mut node = Some(index);
+            while let Some(index) = node {
+                stack.push(self.calls[index].entry);
+                node = self.calls[index].parent;
+            }
+            stack.reverse();
+
+            stacks.push((stack, call.instructions));
+        }
+
+        stacks
+    }
+
+    /// Every call, ordered by when it started, if the profile was created
+    /// [`with_spans`](Profile::with_spans). The whole run is a span at depth 0 and calls
+    /// that have not returned yet last until now.
+    pub fn spans(&self) -> Option<Vec<Span>> {
+        let mut spans = self.spans.clone()?;
+
+        spans.push(Span {
+            entry: self.calls[0].entry,
+            depth: 0,
+            start: 0,
+            duration: self.instructions,
+        });
+        for (index, frame) in self.stack.iter().enumerate() {
+            spans.push(Span {
+                entry: frame.entry,
+                depth: index + 1,
+                start: frame.start,
+                duration: self.instructions - frame.start,
+            });
+        }
+        spans.sort_by_key(|span| (span.start, span.depth));
+
+        Some(spans)
+    }
+
+    /// Writes a ranked, human-readable report. `memory` provides the instructions at the hot
+    /// spots and, if counted, the accesses per region.
+    pub fn write_report(
+        &self,
+        out: &mut impl Write,
+        memory: &Memory,
+        symbols: Option<&SymbolTable>,
+    ) -> io::Result<()> {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+
+        writeln!(out, "profile: {} instructions", self.instructions)?;
+
+        writeln!(out)?;
+        writeln!(out, "hot spots:")?;
+        writeln!(
+            out,
+            "{:>12} {:>7}  {:<12} instruction",
+            "count", "%", "address"
+        )?;
+        for (addr, count) in self.hot_spots().into_iter().take(HOT_SPOTS) {
+            writeln!(
+                out,
+                "{:>12} {:>6.1}%  {:<12} {}",
+                count,
+                percent(count),
+                format_address(symbols, addr),
+                disassemble(memory, addr, symbols)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "opcodes:")?;
+        for (opcode, count) in self.opcodes() {
+            writeln!(
+                out,
+                "  {:<5} {:>12} {:>6.1}%",
+                format!("{:?}", opcode),
+                count,
+                percent(count)
+            )?;
+        }
+
+        if let Some(accesses) = memory.accesses() {
+            writeln!(out)?;
+            writeln!(out, "memory accesses:")?;
+            writeln!(out, "  {:<18} {:>12} {:>12}", "region", "reads", "writes")?;
+            for region in Region::ALL {
+                writeln!(
+                    out,
+                    "  {:<18} {:>12} {:>12}",
+                    region.to_string(),
+                    accesses.reads(region),
+                    accesses.writes(region)
+                )?;
+            }
+        }
+
+        let subroutines = self.subroutines();
+        if !subroutines.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "subroutines:")?;
+            writeln!(
+                out,
+                "{:>12} {:>12} {:>7} {:>12}  subroutine",
+                "calls", "inclusive", "%", "self"
+            )?;
+            for (entry, stats) in subroutines {
+                writeln!(
+                    out,
+                    "{:>12} {:>12} {:>6.1}% {:>12}  {}",
+                    stats.calls,
+                    stats.inclusive,
+                    percent(stats.inclusive),
+                    stats.exclusive,
+                    format_address(symbols, entry)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The whole profile, every executed address included, as JSON.
+    pub fn to_json(&self, memory: &Memory, symbols: Option<&SymbolTable>) -> Value {
+        let addresses: Vec<Value> = self
+            .hot_spots()
+            .into_iter()
+            .map(|(addr, count)| {
+                json!({
+                    "address": format!("x{:04X}", addr),
+                    "location": format_address(symbols, addr),
+                    "instruction": disassemble(memory, addr, symbols),
+                    "count": count,
+                })
+            })
+            .collect();
+
+        let opcodes: serde_json::Map<String, Value> = self
+            .opcodes()
+            .into_iter()
+            .map(|(opcode, count)| (format!("{:?}", opcode), json!(count)))
+            .collect();
+
+        let regions: Vec<Value> = match memory.accesses() {
+            Some(accesses) => Region::ALL
+                .into_iter()
+                .map(|region| {
+                    let (start, end) = region.bounds();
+                    json!({
+                        "region": region.to_string(),
+                        "start": format!("x{:04X}", start),
+                        "end": format!("x{:04X}", end),
+                        "reads": accesses.reads(region),
+                        "writes": accesses.writes(region),
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let subroutines: Vec<Value> = self
+            .subroutines()
+            .into_iter()
+            .map(|(entry, stats)| {
+                json!({
+                    "address": format!("x{:04X}", entry),
+                    "location": format_address(symbols, entry),
+                    "calls": stats.calls,
+                    "inclusive": stats.inclusive,
+                    "self": stats.exclusive,
+                })
+            })
+            .collect();
+
+        json!({
+            "instructions": self.instructions,
+            "addresses": addresses,
+            "opcodes": opcodes,
+            "memory": regions,
+            "subroutines": subroutines,
+        })
+    }
+}
+
+fn disassemble(memory: &Memory, addr: u16, symbols: Option<&SymbolTable>) -> String {
+    let bits = memory.peek(addr);
+
+    match Instruction::try_from(bits) {
+        Ok(instruction) => instruction.at(addr, symbols).to_string(),
+        Err(_) => format!(".FILL x{:04X}", bits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{Add, Jmp, Jsr};
+    use crate::registers::Register;
+
+    #[test]
+    fn test_counts_executions() {
+        let mut profile = Profile::default();
+        let add = Instruction::Add(Add::immediate(Register::R0, Register::R0, 1).unwrap());
+
+        profile.record_execution(0x3000, add.encode());
+        profile.record_execution(0x3000, add.encode());
+        profile.record_execution(0x3001, 0xF025);
+
+        assert_eq!(profile.instructions(), 3);
+        assert_eq!(profile.hot_spots(), vec![(0x3000, 2), (0x3001, 1)]);
+        assert_eq!(profile.opcodes(), vec![(Opcode::ADD, 2), (Opcode::TRAP, 1)]);
+    }
+
+    #[test]
+    fn test_tracks_subroutines() {
+        let mut profile = Profile::default();
+        let call = Instruction::Jsr(Jsr::offset(0x0F).unwrap());
+        let ret = Instruction::Jmp(Jmp::new(Register::R7).unwrap());
+        let add = Instruction::Add(Add::immediate(Register::R0, Register::R0, 1).unwrap());
+
+        // x3000: JSR SUB, twice through x3010: ADD, x3011: RET
+        for pc in [0x3000, 0x3001] {
+            profile.record_execution(pc, call.encode());
+            profile.record_control_flow(pc, &call, 0x3010);
+            profile.record_execution(0x3010, add.encode());
+            profile.record_control_flow(0x3010, &add, 0x3011);
+            profile.record_execution(0x3011, ret.encode());
+            profile.record_control_flow(0x3011, &ret, pc + 1);
+        }
+
+        assert_eq!(
+            profile.subroutines(),
+            vec![(
+                0x3010,
+                SubroutineStats {
+                    calls: 2,
+                    inclusive: 4,
+                    exclusive: 4,
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_return_is_ignored() {
+        let mut profile = Profile::default();
+        let ret = Instruction::Jmp(Jmp::new(Register::R7).unwrap());
+
+        profile.record_execution(0x3000, ret.encode());
+        profile.record_control_flow(0x3000, &ret, 0x4000);
+
+        assert!(profile.subroutines().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::memory::{DEVICE_SPACE_START, SYSTEM_SPACE_END};
+
+/// How an instruction touches a memory location.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read from"),
+            Access::Write => write!(f, "write to"),
+            Access::Execute => write!(f, "execution at"),
+        }
+    }
+}
+
+/// What a protected range forbids.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protection {
+    /// Code and constants that the program must not overwrite.
+    ReadOnly,
+    /// Data that the program must not run.
+    NoExecute,
+}
+
+impl Protection {
+    fn forbids(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Protection::ReadOnly, Access::Write) | (Protection::NoExecute, Access::Execute)
+        )
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protection::ReadOnly => write!(f, "read-only"),
+            Protection::NoExecute => write!(f, "no-execute"),
+        }
+    }
+}
+
+/// Ranges of memory that forbid some accesses, in any privilege mode.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtectionMap {
+    ranges: Vec<(u16, u16, Protection)>,
+}
+
+impl ProtectionMap {
+    /// Applies `protection` from `from` to `to`, both inclusive.
+    pub fn protect(&mut self, from: u16, to: u16, protection: Protection) -> Result<(), String> {
+        if from > to {
+            return Err(format!("x{:04X} comes after x{:04X}", from, to));
+        }
+        self.ranges.push((from, to, protection));
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The protection that forbids `access` to `addr`, if any.
+    pub fn check(&self, addr: u16, access: Access) -> Option<Protection> {
+        self.ranges
+            .iter()
+            .find(|&&(from, to, protection)| {
+                (from..=to).contains(&addr) && protection.forbids(access)
+            })
+            .map(|&(_, _, protection)| protection)
+    }
+}
+
+/// Whether `addr` is in the system area or the device registers, which user mode cannot
+/// access.
+pub fn is_privileged(addr: u16) -> bool {
+    addr <= SYSTEM_SPACE_END || addr >= DEVICE_SPACE_START
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protection_map() {
+        let mut map = ProtectionMap::default();
+        map.protect(0x3000, 0x30FF, Protection::ReadOnly).unwrap();
+        map.protect(0x4000, 0x4000, Protection::NoExecute).unwrap();
+
+        assert_eq!(map.check(0x30FF, Access::Write), Some(Protection::ReadOnly));
+        assert_eq!(map.check(0x30FF, Access::Read), None);
+        assert_eq!(map.check(0x30FF, Access::Execute), None);
+        assert_eq!(map.check(0x3100, Access::Write), None);
+        assert_eq!(
+            map.check(0x4000, Access::Execute),
+            Some(Protection::NoExecute)
+        );
+        assert_eq!(map.check(0x4000, Access::Write), None);
+
+        assert!(map.protect(0x5000, 0x4FFF, Protection::ReadOnly).is_err());
+    }
+
+    #[test]
+    fn test_is_privileged() {
+        assert!(is_privileged(0x0000));
+        assert!(is_privileged(0x2FFF));
+        assert!(!is_privileged(0x3000));
+        assert!(!is_privileged(0xFDFF));
+        assert!(is_privileged(0xFE00));
+        assert!(is_privileged(0xFFFF));
+    }
+}
@@ -1,3 +1,12 @@
+#[cfg(feature = "alloc")]
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::str::FromStr;
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Register {
     R0,
@@ -12,6 +21,7 @@ pub enum Register {
     COND,
 }
 
+#[cfg(feature = "alloc")]
 impl TryFrom<u16> for Register {
     type Error = String;
 
@@ -32,6 +42,50 @@ impl TryFrom<u16> for Register {
     }
 }
 
+impl Register {
+    /// The general purpose register numbered by the low three bits of `bits`, as in the
+    /// register fields of an instruction.
+    pub fn from_bits(bits: u16) -> Self {
+        match bits & 0b111 {
+            0 => Self::R0,
+            1 => Self::R1,
+            2 => Self::R2,
+            3 => Self::R3,
+            4 => Self::R4,
+            5 => Self::R5,
+            6 => Self::R6,
+            _ => Self::R7,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PC => write!(f, "PC"),
+            Self::COND => write!(f, "COND"),
+            general => write!(f, "R{}", *general as u16),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "PC" => Ok(Self::PC),
+            name => name
+                .strip_prefix('R')
+                .and_then(|n| n.parse::<u16>().ok())
+                .filter(|&n| n < 8)
+                .map(Register::from_bits)
+                .ok_or_else(|| format!("{} is not a register", s)),
+        }
+    }
+}
+
 pub enum MemoryMappedReg {
     Kbsr = 0xFE00,
     Kbdr = 0xFE02,
@@ -49,6 +103,8 @@ pub struct Registers {
     r7: u16,
     pc: u16,
     cond: u16,
+    /// Privilege and priority bits of the PSR; the condition codes live in `cond`.
+    psr: u16,
 }
 
 impl Registers {
@@ -83,7 +139,12 @@ impl Registers {
     }
 
     pub fn update_flags(&mut self, register: Register) {
-        let flag = match self.get(register) {
+        self.set_flags(self.get(register));
+    }
+
+    /// Sets the condition codes from the sign of `value`.
+    pub fn set_flags(&mut self, value: u16) {
+        let flag = match value {
             0 => CondFlag::ZRO,
             x if x >> 15 == 1 => CondFlag::NEG,
             _ => CondFlag::POS,
@@ -92,8 +153,42 @@ impl Registers {
         self.set(Register::COND, flag as u16);
     }
 
+    /// R0-R7, indexed by register number.
+    pub fn general(&self) -> [u16; 8] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7,
+        ]
+    }
+
+    pub fn set_general(&mut self, values: [u16; 8]) {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7,
+        ] = values;
+    }
+
+    /// The processor status register: privilege in bit 15, priority in bits 10-8 and the
+    /// condition codes in bits 2-0.
+    pub fn psr(&self) -> u16 {
+        self.psr | self.cond
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn set_psr(&mut self, psr: u16) -> Result<(), String> {
+        let cond = CondFlag::try_from(psr & 0b111)?;
+
+        self.psr = psr & 0b1000_0111_0000_0000;
+        self.cond = cond as u16;
+
+        Ok(())
+    }
+
+    /// Whether the PSR is in user mode (bit 15 set) rather than supervisor mode.
+    pub fn is_user_mode(&self) -> bool {
+        self.psr & 0x8000 != 0
+    }
+
     pub fn program_counter_increment(&mut self) {
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
     }
 }
 
@@ -104,6 +199,7 @@ pub enum CondFlag {
     NEG = 1 << 2,
 }
 
+#[cfg(feature = "alloc")]
 impl TryFrom<u16> for CondFlag {
     type Error = String;
 
@@ -112,18 +208,19 @@ impl TryFrom<u16> for CondFlag {
             value if value == (1 << 0) => Ok(CondFlag::POS),
             value if value == (1 << 1) => Ok(CondFlag::ZRO),
             value if value == (1 << 2) => Ok(CondFlag::NEG),
-            _ => Err(format!("{} is not a condition flag.", value).to_owned()),
+            _ => Err(format!("{} is not a condition flag.", value)),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::u16;
+    use alloc::string::ToString;
 
     use super::*;
 
     #[test]
+    #[cfg(feature = "alloc")]
     fn test_value_to_register_should_return_register_variant() {
         assert_eq!(
             Register::try_from(0b0000_0000_0000_0000).unwrap(),
@@ -144,10 +241,56 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "alloc")]
     fn test_value_to_unexistant_register_should_fail() {
         assert!(Register::try_from(0b0000_0000_0000_1111).is_err())
     }
 
+    #[test]
+    fn test_register_from_bits_uses_low_three_bits() {
+        assert_eq!(Register::from_bits(0), Register::R0);
+        assert_eq!(Register::from_bits(0b1111_1111_1111_1101), Register::R5);
+    }
+
+    #[test]
+    fn test_program_counter_increment_wraps() {
+        let mut registers = Registers::default();
+        registers.set(Register::PC, 0xFFFF);
+
+        registers.program_counter_increment();
+
+        assert_eq!(registers.get(Register::PC), 0x0000);
+    }
+
+    #[test]
+    fn test_register_display() {
+        assert_eq!(Register::R0.to_string(), "R0");
+        assert_eq!(Register::R7.to_string(), "R7");
+        assert_eq!(Register::PC.to_string(), "PC");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_register_from_str() {
+        assert_eq!("R3".parse(), Ok(Register::R3));
+        assert_eq!("r7".parse(), Ok(Register::R7));
+        assert_eq!("pc".parse(), Ok(Register::PC));
+        assert!("R8".parse::<Register>().is_err());
+        assert!("COND".parse::<Register>().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_psr() {
+        let mut registers = Registers::default();
+
+        registers.set_psr(0x8402).unwrap();
+
+        assert_eq!(registers.psr(), 0x8402);
+        assert_eq!(registers.get(Register::COND), CondFlag::ZRO as u16);
+        assert!(registers.set_psr(0x8003).is_err());
+    }
+
     #[test]
     fn test_get_register_gets_correctly() {
         let registers = Registers {
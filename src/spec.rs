@@ -0,0 +1,873 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    console::Scripted,
+    formats,
+    image::Image,
+    loader,
+    memory::Memory,
+    registers::{Register, Registers},
+    symbols::{format_address, SymbolTable},
+    utils::parse_number,
+    vm::{Stop, Vm, RETURN_SENTINEL},
+};
+
+/// How many instructions a case may run when neither it nor the spec sets a limit, so that
+/// a program stuck in a loop fails instead of hanging the run.
+pub const DEFAULT_INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+/// Where the stack starts when a case pushes onto it without setting R6: just below the
+/// device registers, growing down.
+pub const STACK_BASE: u16 = 0xFE00;
+
+/// Test cases for LC-3 programs, read from a TOML or YAML file:
+///
+/// ```toml
+/// images = ["add.obj"]
+///
+/// [[cases]]
+/// name = "adds two numbers"
+/// registers = { R1 = 2, R2 = "#-5" }
+/// memory = { DATA = [1, 2, 3] }
+/// input = "y\n"
+/// expect = { output = "done\n", registers = { R0 = "xFFFD" }, memory = { RESULT = 6 } }
+/// ```
+///
+/// Each case loads the images, with the labels from their `.sym` files, sets up registers,
+/// memory and keyboard input, runs to HALT and compares the output, registers and memory
+/// with what it expects. Values and addresses are numbers, `x`/`#`/`b` literals or labels.
+///
+/// A case can instead call a single subroutine, with arguments in registers or pushed on
+/// the stack, and check what it returns and that it left the caller's registers alone:
+///
+/// ```toml
+/// [[cases]]
+/// name = "multiplies"
+/// call = "MULTIPLY"
+/// stack = [6, 7]
+/// expect = { registers = { R0 = 42 }, preserved = ["R1", "R2", "R6"] }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    /// Images for cases that do not list their own, relative to the spec file.
+    #[serde(default)]
+    pub images: Vec<PathBuf>,
+    /// The limit for cases that do not set one [default: [`DEFAULT_INSTRUCTION_LIMIT`]].
+    pub instruction_limit: Option<u64>,
+    pub cases: Vec<Case>,
+    /// Where image paths are relative to.
+    #[serde(skip)]
+    base: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    #[serde(default)]
+    pub images: Vec<PathBuf>,
+    /// Where to start [default: the origin of the first image].
+    pub pc: Option<Value>,
+    /// A subroutine to call, as `JSR` would, and run until it returns rather than to HALT.
+    pub call: Option<Value>,
+    /// Words to push before starting, the first on top: R6 points at it. When the case
+    /// calls a subroutine or pushes, R6 starts at [`STACK_BASE`] unless the case sets it.
+    #[serde(default)]
+    pub stack: Vec<Value>,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    /// The processor status register, e.g. x8002 to run in user mode, where touching the
+    /// system area or the device registers is an access control violation.
+    pub psr: Option<Value>,
+    /// Words to store from each address, after loading the images.
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    /// The keys the program reads; asking for more is an error.
+    #[serde(default)]
+    pub input: String,
+    pub instruction_limit: Option<u64>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+/// What a case checks once the program halts, or the subroutine it calls returns. Registers
+/// and memory it does not mention can hold anything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// Everything the program printed.
+    pub output: Option<String>,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    /// Registers that must hold what they held at the start, such as callee-saved ones.
+    #[serde(default)]
+    pub preserved: Vec<String>,
+    /// The words on the stack, from the one R6 points at up.
+    #[serde(default)]
+    pub stack: Vec<Value>,
+}
+
+/// A word, as a number or as text to parse: a literal or a label.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+/// One word or several consecutive ones.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Words {
+    One(Value),
+    Many(Vec<Value>),
+}
+
+/// When a case gives up on a program that does not halt.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub instructions: u64,
+    /// Raised from another thread once the case has run for too long.
+    pub timeout: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The program halted, or the subroutine returned, with the wrong results.
+    Failed(Vec<String>),
+    /// The case could not be set up or the program did not finish.
+    Error(String),
+}
+
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub instructions: u64,
+    pub output: String,
+    pub duration: Duration,
+}
+
+impl CaseResult {
+    /// A result for `case` when it could not run at all.
+    pub fn error(case: &Case, e: String) -> Self {
+        Self {
+            name: case.name.clone(),
+            outcome: Outcome::Error(e),
+            instructions: 0,
+            output: String::new(),
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+impl Value {
+    /// The word, from a number between -32768 and 65535 or a literal or label in `symbols`.
+    pub fn resolve(&self, symbols: &SymbolTable) -> Result<u16, String> {
+        let (number, text) = match self {
+            Value::Number(n) => (Some(*n), n.to_string()),
+            Value::Text(text) => (parse_number(text).map(i64::from), text.clone()),
+        };
+
+        match number {
+            Some(n) if (-0x8000..=0xFFFF).contains(&n) => Ok(n as u16),
+            Some(_) => Err(format!("{} does not fit in a word", text)),
+            None => symbols.resolve(&text),
+        }
+    }
+}
+
+impl Words {
+    pub fn resolve(&self, symbols: &SymbolTable) -> Result<Vec<u16>, String> {
+        match self {
+            Words::One(value) => Ok(vec![value.resolve(symbols)?]),
+            Words::Many(values) => values.iter().map(|value| value.resolve(symbols)).collect(),
+        }
+    }
+}
+
+impl Spec {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, String> {
+        serde_yaml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Reads a `.toml`, `.yaml` or `.yml` spec, whose image paths are relative to it.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let spec = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Err(String::from("expected a .toml, .yaml or .yml file")),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(Self {
+            base: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            ..spec
+        })
+    }
+
+    pub fn run(&self) -> Vec<CaseResult> {
+        self.cases.iter().map(|case| self.run_case(case)).collect()
+    }
+
+    /// Loads the images of `case` and runs it.
+    pub fn run_case(&self, case: &Case) -> CaseResult {
+        match self.load_images(case) {
+            Ok((images, symbols)) => case.run(&images, &symbols, self.limits(case)),
+            Err(e) => CaseResult::error(case, e),
+        }
+    }
+
+    /// The images `case` lists, or else those of the spec, with their labels.
+    pub fn load_images(&self, case: &Case) -> Result<(Vec<Image>, SymbolTable), String> {
+        if case.images.is_empty() {
+            load_images(&self.base, &self.images)
+        } else {
+            load_images(&self.base, &case.images)
+        }
+    }
+
+    pub fn limits(&self, case: &Case) -> Limits {
+        Limits {
+            instructions: case
+                .instruction_limit
+                .or(self.instruction_limit)
+                .unwrap_or(DEFAULT_INSTRUCTION_LIMIT),
+            timeout: None,
+        }
+    }
+}
+
+/// Reads the images at `paths`, relative to `base`, and the labels from the `.sym` file
+/// next to each one that has one.
+pub fn load_images(base: &Path, paths: &[PathBuf]) -> Result<(Vec<Image>, SymbolTable), String> {
+    let mut images = Vec::new();
+    let mut symbols = SymbolTable::default();
+    for path in paths {
+        let path = base.join(path);
+        images.extend(formats::read_file(&path, None)?);
+
+        let sym_file = path.with_extension("sym");
+        if sym_file.exists() {
+            symbols.merge(&SymbolTable::from_file(&sym_file)?);
+        }
+    }
+
+    Ok((images, symbols))
+}
+
+impl Case {
+    /// Runs the case on `images`, loaded in order, giving up at the `limits`.
+    pub fn run(&self, images: &[Image], symbols: &SymbolTable, limits: Limits) -> CaseResult {
+        let start = Instant::now();
+        let output = Capture::default();
+
+        let (outcome, instructions) = match self.prepare(images, symbols, output.clone()) {
+            Ok(mut vm) => {
+                vm.set_instruction_limit(limits.instructions);
+                if let Some(timeout) = limits.timeout {
+                    vm.set_interrupt(timeout);
+                }
+                let initial = vm.registers().clone();
+                let stop = match &self.call {
+                    Some(entry) => entry
+                        .resolve(symbols)
+                        .and_then(|entry| vm.call(entry, RETURN_SENTINEL)),
+                    None => vm.resume(),
+                };
+                let finished = if self.call.is_some() {
+                    Stop::Returned
+                } else {
+                    Stop::Halted
+                };
+                let outcome = match stop {
+                    Ok(stop) if stop == finished => match self.check(&vm, &initial, &output.text())
+                    {
+                        Ok(failures) if failures.is_empty() => Outcome::Passed,
+                        Ok(failures) => Outcome::Failed(failures),
+                        Err(e) => Outcome::Error(e),
+                    },
+                    Ok(stop) => Outcome::Error(unfinished(
+                        stop,
+                        &format_address(Some(symbols), vm.registers().get(Register::PC)),
+                    )),
+                    Err(e) => Outcome::Error(e),
+                };
+                (outcome, vm.instructions())
+            }
+            Err(e) => (Outcome::Error(e), 0),
+        };
+
+        CaseResult {
+            name: self.name.clone(),
+            outcome,
+            instructions,
+            output: output.text(),
+            duration: start.elapsed(),
+        }
+    }
+
+    fn prepare(
+        &self,
+        images: &[Image],
+        symbols: &SymbolTable,
+        output: Capture,
+    ) -> Result<Vm, String> {
+        if images.is_empty() {
+            return Err(String::from("no images to load"));
+        }
+        if self.pc.is_some() && self.call.is_some() {
+            return Err(String::from("a case cannot set both pc and call"));
+        }
+
+        let mut registers = Registers::default();
+        if self.call.is_some() || !self.stack.is_empty() {
+            registers.set(Register::R6, STACK_BASE);
+        }
+        for (register, value) in &self.registers {
+            registers.set(register.parse()?, value.resolve(symbols)?);
+        }
+        if let Some(psr) = &self.psr {
+            registers.set_psr(psr.resolve(symbols)?)?;
+        }
+
+        let mut memory = Memory::default();
+        loader::load(&mut memory, images);
+        for (addr, words) in &self.memory {
+            let addr = symbols.resolve(addr)?;
+            for (offset, word) in words.resolve(symbols)?.into_iter().enumerate() {
+                memory.write(addr.wrapping_add(offset as u16), word);
+            }
+        }
+        let top = registers
+            .get(Register::R6)
+            .wrapping_sub(self.stack.len() as u16);
+        for (offset, value) in self.stack.iter().enumerate() {
+            memory.write(top.wrapping_add(offset as u16), value.resolve(symbols)?);
+        }
+        registers.set(Register::R6, top);
+        memory.set_console(Box::new(Scripted::new(self.input.as_bytes(), output)));
+
+        let mut vm = Vm::new(registers, memory);
+        if let Some(pc) = &self.pc {
+            vm.set_pc(pc.resolve(symbols)?);
+        }
+        vm.set_symbols(symbols.clone());
+
+        Ok(vm)
+    }
+
+    /// Compares the finished `vm`, which started with the `initial` registers, and what it
+    /// printed with what the case expects.
+    fn check(&self, vm: &Vm, initial: &Registers, output: &str) -> Result<Vec<String>, String> {
+        let symbols = vm.symbols().unwrap();
+        let mut failures = Vec::new();
+
+        if let Some(expected) = &self.expect.output {
+            if output != expected {
+                failures.push(format!("output: expected {:?}, got {:?}", expected, output));
+            }
+        }
+
+        for (register, value) in &self.expect.registers {
+            let register: Register = register.parse()?;
+            let expected = value.resolve(symbols)?;
+            let actual = vm.registers().get(register);
+            if actual != expected {
+                failures.push(format!(
+                    "{}: expected x{:04X}, got x{:04X}",
+                    register, expected, actual
+                ));
+            }
+        }
+
+        for (addr, words) in &self.expect.memory {
+            let addr = symbols.resolve(addr)?;
+            for (offset, expected) in words.resolve(symbols)?.into_iter().enumerate() {
+                let addr = addr.wrapping_add(offset as u16);
+                let actual = vm.memory().peek(addr);
+                if actual != expected {
+                    failures.push(format!(
+                        "{}: expected x{:04X}, got x{:04X}",
+                        format_address(Some(symbols), addr),
+                        expected,
+                        actual
+                    ));
+                }
+            }
+        }
+
+        for register in &self.expect.preserved {
+            let register: Register = register.parse()?;
+            let (before, after) = (initial.get(register), vm.registers().get(register));
+            if before != after {
+                failures.push(format!(
+                    "{}: not preserved, was x{:04X}, now x{:04X}",
+                    register, before, after
+                ));
+            }
+        }
+
+        let top = vm.registers().get(Register::R6);
+        for (offset, expected) in self.expect.stack.iter().enumerate() {
+            let expected = expected.resolve(symbols)?;
+            let actual = vm.memory().peek(top.wrapping_add(offset as u16));
+            if actual != expected {
+                failures.push(format!(
+                    "stack[{}]: expected x{:04X}, got x{:04X}",
+                    offset, expected, actual
+                ));
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+/// Why a case stopped at `at` without finishing: the timeout raising the interrupt flag, or
+/// any other stop by name so that it is not mistaken for one.
+fn unfinished(stop: Stop, at: &str) -> String {
+    match stop {
+        Stop::Interrupted => format!("timed out at {}", at),
+        Stop::Halted => String::from("halted before returning"),
+        stop => format!("stopped ({}) at {}", stop, at),
+    }
+}
+
+/// Keeps what the program prints, for the test to read once the VM is done with it.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Outcome {
+    fn status(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "pass",
+            Outcome::Failed(_) => "fail",
+            Outcome::Error(_) => "error",
+        }
+    }
+}
+
+/// One line per case, with the reasons it failed below it, and the totals.
+pub fn write_report(out: &mut impl Write, results: &[CaseResult]) -> io::Result<()> {
+    for result in results {
+        writeln!(
+            out,
+            "{:<5} {}",
+            result.outcome.status().to_uppercase(),
+            result.name
+        )?;
+        match &result.outcome {
+            Outcome::Passed => {}
+            Outcome::Failed(failures) => {
+                for failure in failures {
+                    writeln!(out, "      {}", failure)?;
+                }
+            }
+            Outcome::Error(e) => writeln!(out, "      {}", e)?,
+        }
+    }
+
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.outcome.status() == status)
+            .count()
+    };
+    writeln!(
+        out,
+        "{} cases: {} passed, {} failed, {} errors",
+        results.len(),
+        count("pass"),
+        count("fail"),
+        count("error")
+    )
+}
+
+pub fn to_json(results: &[CaseResult]) -> Json {
+    let cases: Vec<Json> = results
+        .iter()
+        .map(|result| {
+            let (failures, error) = match &result.outcome {
+                Outcome::Passed => (&[][..], None),
+                Outcome::Failed(failures) => (&failures[..], None),
+                Outcome::Error(e) => (&[][..], Some(e)),
+            };
+            json!({
+                "name": result.name,
+                "status": result.outcome.status(),
+                "failures": failures,
+                "error": error,
+                "instructions": result.instructions,
+                "output": result.output,
+                "seconds": result.duration.as_secs_f64(),
+            })
+        })
+        .collect();
+
+    json!({
+        "passed": results.iter().filter(|result| result.outcome == Outcome::Passed).count(),
+        "total": results.len(),
+        "cases": cases,
+    })
+}
+
+/// Writes the results as a JUnit XML test suite named `suite`, for CI servers.
+pub fn write_junit(out: &mut impl Write, suite: &str, results: &[CaseResult]) -> io::Result<()> {
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.outcome.status() == status)
+            .count()
+    };
+    let seconds: f64 = results
+        .iter()
+        .map(|result| result.duration.as_secs_f64())
+        .sum();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="{}" tests="{}" failures="{}" errors="{}" time="{:.6}">"#,
+        escape_xml(suite),
+        results.len(),
+        count("fail"),
+        count("error"),
+        seconds
+    )?;
+    for result in results {
+        write!(
+            out,
+            r#"  <testcase name="{}" classname="{}" time="{:.6}">"#,
+            escape_xml(&result.name),
+            escape_xml(suite),
+            result.duration.as_secs_f64()
+        )?;
+        match &result.outcome {
+            Outcome::Passed => {}
+            Outcome::Failed(failures) => write!(
+                out,
+                r#"<failure message="{}">{}</failure>"#,
+                escape_xml(&failures[0]),
+                escape_xml(&failures.join("\n"))
+            )?,
+            Outcome::Error(e) => write!(out, r#"<error message="{}"/>"#, escape_xml(e))?,
+        }
+        if !result.output.is_empty() {
+            write!(
+                out,
+                "<system-out>{}</system-out>",
+                escape_xml(&result.output)
+            )?;
+        }
+        writeln!(out, "</testcase>")?;
+    }
+    writeln!(out, "</testsuite>")
+}
+
+/// Escapes text for XML attributes and content, dropping the control characters XML 1.0
+/// cannot hold.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\t' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble, Assembly};
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        ADD R3, R1, R2
+        ST R3, SUM
+        GETC
+        OUT
+        HALT
+        SUM .BLKW 1
+        .END
+        ";
+
+    const SPEC: &str = r##"
+        instruction_limit = 100
+
+        [[cases]]
+        name = "adds"
+        registers = { R1 = 2, R2 = "#-5" }
+        input = "y"
+        expect = { output = "y", registers = { R3 = "xFFFD" }, memory = { SUM = -3 } }
+
+        [[cases]]
+        name = "wrong sum"
+        registers = { R1 = 2 }
+        input = "n"
+        expect = { output = "y", registers = { R3 = 3 }, memory = { SUM = [2, 1] } }
+
+        [[cases]]
+        name = "no input"
+        "##;
+
+    /// Runs every case of `spec` on the assembled program.
+    fn run(spec: &Spec, assembly: &Assembly) -> Vec<CaseResult> {
+        spec.cases
+            .iter()
+            .map(|case| {
+                case.run(
+                    std::slice::from_ref(&assembly.image),
+                    &assembly.symbols,
+                    spec.limits(case),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_run_cases() {
+        let results = run(&Spec::from_toml(SPEC).unwrap(), &assemble(PROGRAM).unwrap());
+
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(results[0].output, "y");
+        assert_eq!(results[0].instructions, 5);
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Failed(vec![
+                String::from(r#"output: expected "y", got "n""#),
+                String::from("R3: expected x0003, got x0002"),
+                String::from("SUM+1: expected x0001, got x0000"),
+            ])
+        );
+        assert_eq!(
+            results[2].outcome,
+            Outcome::Error(String::from("input ended at x3002"))
+        );
+    }
+
+    #[test]
+    fn test_yaml_and_limits() {
+        let spec = Spec::from_yaml(
+            "
+            cases:
+              - name: loops
+                pc: x3004
+                memory: { x3004: x0FFF }
+                instruction_limit: 10
+            ",
+        )
+        .unwrap();
+
+        let results = run(&spec, &assemble(PROGRAM).unwrap());
+        assert_eq!(
+            results[0].outcome,
+            Outcome::Error(String::from("stopped after 10 instructions at x3004"))
+        );
+
+        assert!(Spec::from_toml("cases = []\nimage = []").is_err());
+    }
+
+    #[test]
+    fn test_unfinished_cases() {
+        let spec = Spec::from_toml("[[cases]]\nname = \"slow\"").unwrap();
+        let assembly = assemble(PROGRAM).unwrap();
+        let limits = Limits {
+            instructions: 100,
+            timeout: Some(Arc::new(AtomicBool::new(true))),
+        };
+
+        let result = spec.cases[0].run(
+            std::slice::from_ref(&assembly.image),
+            &assembly.symbols,
+            limits,
+        );
+
+        assert_eq!(
+            result.outcome,
+            Outcome::Error(String::from("timed out at x3000"))
+        );
+        assert_eq!(
+            unfinished(Stop::Breakpoint(0x3001), "x3001"),
+            "stopped (breakpoint) at x3001"
+        );
+        assert_eq!(
+            unfinished(Stop::Returned, "x3005"),
+            "stopped (returned) at x3005"
+        );
+    }
+
+    #[test]
+    fn test_call_subroutines() {
+        let assembly = assemble(
+            "
+            .ORIG x3000
+            HALT
+            MULTIPLY ADD R6, R6, #-1
+            STR R1, R6, #0
+            ADD R6, R6, #-1
+            STR R2, R6, #0
+            LDR R1, R6, #2
+            LDR R2, R6, #3
+            AND R0, R0, #0
+            LOOP ADD R0, R0, R1
+            ADD R2, R2, #-1
+            BRp LOOP
+            LDR R2, R6, #0
+            LDR R1, R6, #1
+            ADD R6, R6, #2
+            RET
+            CLOBBER AND R1, R1, #0
+            ADD R6, R6, #1
+            RET
+            .END
+            ",
+        )
+        .unwrap();
+        let spec = Spec::from_toml(
+            r#"
+            [[cases]]
+            name = "multiplies"
+            call = "MULTIPLY"
+            registers = { R1 = 1, R2 = 2 }
+            stack = [6, 7]
+            expect = { registers = { R0 = 42 }, preserved = ["R1", "R2", "R6"], stack = [6, 7] }
+
+            [[cases]]
+            name = "clobbers"
+            call = "CLOBBER"
+            registers = { R1 = 5, R6 = "x4000" }
+            expect = { preserved = ["R1", "R6"] }
+
+            [[cases]]
+            name = "halts"
+            call = "x3000"
+
+            [[cases]]
+            name = "both"
+            pc = "x3000"
+            call = "MULTIPLY"
+            "#,
+        )
+        .unwrap();
+
+        let results = run(&spec, &assembly);
+
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Failed(vec![
+                String::from("R1: not preserved, was x0005, now x0000"),
+                String::from("R6: not preserved, was x4000, now x4001"),
+            ])
+        );
+        assert_eq!(
+            results[2].outcome,
+            Outcome::Error(String::from("halted before returning"))
+        );
+        assert_eq!(
+            results[3].outcome,
+            Outcome::Error(String::from("a case cannot set both pc and call"))
+        );
+    }
+
+    #[test]
+    fn test_user_mode() {
+        let assembly = assemble(".ORIG x3000\nMAIN STR R0, R1, #0\nHALT\n.END").unwrap();
+        let spec = Spec::from_toml(
+            r#"
+            [[cases]]
+            name = "supervisor"
+            registers = { R0 = 7, R1 = "x0025" }
+            expect = { memory = { x0025 = 7 } }
+
+            [[cases]]
+            name = "user"
+            psr = "x8002"
+            registers = { R0 = 7, R1 = "x0025" }
+            "#,
+        )
+        .unwrap();
+
+        let results = run(&spec, &assembly);
+
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Error(String::from(
+                "access control violation at MAIN (STR R0, R1, #0): user-mode write to x0025"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_reports() {
+        let results = run(&Spec::from_toml(SPEC).unwrap(), &assemble(PROGRAM).unwrap());
+
+        let mut report = Vec::new();
+        write_report(&mut report, &results).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("PASS  adds\nFAIL  wrong sum\n      output: expected"));
+        assert!(report.contains("ERROR no input\n      input ended at x3002\n"));
+        assert!(report.ends_with("3 cases: 1 passed, 1 failed, 1 errors\n"));
+
+        let json = to_json(&results);
+        assert_eq!(json["passed"], 1);
+        assert_eq!(json["cases"][1]["status"], "fail");
+        assert_eq!(json["cases"][2]["error"], "input ended at x3002");
+
+        let mut junit = Vec::new();
+        write_junit(&mut junit, "add", &results).unwrap();
+        let junit = String::from_utf8(junit).unwrap();
+        assert!(junit.contains(r#"<testsuite name="add" tests="3" failures="1" errors="1""#));
+        assert!(junit
+            .contains(r#"<failure message="output: expected &quot;y&quot;, got &quot;n&quot;">"#));
+        assert!(junit.contains(r#"<error message="input ended at x3002"/>"#));
+    }
+}
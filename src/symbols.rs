@@ -0,0 +1,255 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::utils::parse_number;
+
+/// How far past a label an address can be and still be shown relative to it.
+const MAX_LABEL_OFFSET: u16 = 0xFF;
+
+/// Labels and the addresses they refer to, as read from a `.sym` file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymbolTable {
+    addresses: BTreeMap<String, u16>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn insert(&mut self, label: &str, addr: u16) {
+        self.addresses.insert(label.to_string(), addr);
+        self.labels.entry(addr).or_insert_with(|| label.to_string());
+    }
+
+    /// Adds every label of `other`, keeping ours where both define the same label.
+    pub fn merge(&mut self, other: &SymbolTable) {
+        for (label, &addr) in &other.addresses {
+            if self.address(label).is_none() {
+                self.insert(label, addr);
+            }
+        }
+    }
+
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Every label and its address, ordered by label.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.addresses
+            .iter()
+            .map(|(label, &addr)| (label.as_str(), addr))
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// Shows `addr` as a label, `LABEL+offset` relative to the closest label before it, or
+    /// as a plain `x3000` address when there is no label nearby.
+    pub fn format_address(&self, addr: u16) -> String {
+        match self.labels.range(..=addr).next_back() {
+            Some((&label_addr, label)) if addr == label_addr => label.clone(),
+            Some((&label_addr, label)) if addr - label_addr <= MAX_LABEL_OFFSET => {
+                format!("{}+{}", label, addr - label_addr)
+            }
+            _ => format!("x{:04X}", addr),
+        }
+    }
+
+    /// Parses an address written as a number (`x3000`, `#12288`), a label (`MAIN`) or a
+    /// label with an offset (`MAIN+3`, `LOOP-1`).
+    pub fn resolve(&self, text: &str) -> Result<u16, String> {
+        if let Some(n) = parse_number(text) {
+            return u16::try_from(n).map_err(|_| format!("{} is not an address", text));
+        }
+
+        let (label, offset) = match text.find(['+', '-']) {
+            Some(i) => {
+                let offset = parse_number(&text[i + 1..])
+                    .ok_or_else(|| format!("invalid offset in '{}'", text))?;
+                let offset = if text.as_bytes()[i] == b'-' {
+                    -offset
+                } else {
+                    offset
+                };
+                (&text[..i], offset)
+            }
+            None => (text, 0),
+        };
+
+        let addr = self
+            .address(label)
+            .ok_or_else(|| format!("unknown label '{}'", label))?;
+
+        u16::try_from(addr as i32 + offset).map_err(|_| format!("{} is out of range", text))
+    }
+
+    /// Parses a symbol file. Both the table written by `lc3as`
+    ///
+    /// ```text
+    /// // Symbol table
+    /// // Scope level 0:
+    /// //    Symbol Name       Page Address
+    /// //    ----------------  ------------
+    /// //    MAIN              3000
+    /// ```
+    ///
+    /// and the `LABEL x3000` / `LABEL: x3000` / `x3000 LABEL` listings produced by lc3tools
+    /// and most other toolchains are accepted. Lines that are not a label and an address are
+    /// ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = Self::default();
+
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches("//");
+            let fields: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == ':' || c == '=')
+                .filter(|field| !field.is_empty())
+                .collect();
+
+            let [first, second] = fields[..] else {
+                continue;
+            };
+
+            if let (true, Some(addr)) = (is_label(first), parse_hex(second)) {
+                table.insert(first, addr);
+            } else if let (Some(addr), true) = (parse_hex(first), is_label(second)) {
+                if first.starts_with(['x', 'X']) {
+                    table.insert(second, addr);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::parse(&text)
+    }
+
+    /// Writes the table in the `lc3as` format accepted by [`SymbolTable::parse`], ordered by
+    /// address.
+    #[cfg(feature = "std")]
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut symbols: Vec<(&String, &u16)> = self.addresses.iter().collect();
+        symbols.sort_by_key(|&(label, addr)| (*addr, label));
+
+        writeln!(out, "// Symbol table")?;
+        writeln!(out, "// Scope level 0:")?;
+        writeln!(out, "//\tSymbol Name       Page Address")?;
+        writeln!(out, "//\t----------------  ------------")?;
+        for (label, addr) in symbols {
+            writeln!(out, "//\t{:<16}  {:04X}", label, addr)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Like [`SymbolTable::format_address`], falling back to a plain address without symbols.
+pub fn format_address(symbols: Option<&SymbolTable>, addr: u16) -> String {
+    match symbols {
+        Some(symbols) => symbols.format_address(addr),
+        None => format!("x{:04X}", addr),
+    }
+}
+
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses `3000`, `x3000` or `0x3000`.
+fn parse_hex(word: &str) -> Option<u16> {
+    let digits = word
+        .strip_prefix("0x")
+        .or(word.strip_prefix(['x', 'X']))
+        .unwrap_or(word);
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lc3as_symbol_table() {
+        let text = "// Symbol table\n\
+                    // Scope level 0:\n\
+                    //\tSymbol Name       Page Address\n\
+                    //\t----------------  ------------\n\
+                    //\tMAIN              3000\n\
+                    //\tLOOP              3004\n";
+
+        let table = SymbolTable::parse(text).unwrap();
+
+        assert_eq!(table.address("MAIN"), Some(0x3000));
+        assert_eq!(table.address("LOOP"), Some(0x3004));
+        assert_eq!(table.label(0x3004), Some("LOOP"));
+        assert_eq!(table.label(0x3001), None);
+    }
+
+    #[test]
+    fn test_parse_lc3tools_symbol_table() {
+        let text = "MAIN x3000\nLOOP: x3004\nx3010 DATA\n";
+
+        let table = SymbolTable::parse(text).unwrap();
+
+        assert_eq!(table.address("MAIN"), Some(0x3000));
+        assert_eq!(table.address("LOOP"), Some(0x3004));
+        assert_eq!(table.address("DATA"), Some(0x3010));
+    }
+
+    #[test]
+    fn test_format_address() {
+        let mut table = SymbolTable::default();
+        table.insert("MAIN", 0x3000);
+
+        assert_eq!(table.format_address(0x3000), "MAIN");
+        assert_eq!(table.format_address(0x3003), "MAIN+3");
+        assert_eq!(table.format_address(0x2FFF), "x2FFF");
+        assert_eq!(table.format_address(0x4000), "x4000");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut table = SymbolTable::default();
+        table.insert("MAIN", 0x3000);
+
+        assert_eq!(table.resolve("MAIN"), Ok(0x3000));
+        assert_eq!(table.resolve("MAIN+3"), Ok(0x3003));
+        assert_eq!(table.resolve("MAIN-x10"), Ok(0x2FF0));
+        assert_eq!(table.resolve("x4000"), Ok(0x4000));
+        assert!(table.resolve("NOPE+1").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_round_trips() {
+        let mut table = SymbolTable::default();
+        table.insert("MAIN", 0x3000);
+        table.insert("DATA", 0x3010);
+
+        let mut out = Vec::new();
+        table.write(&mut out).unwrap();
+        let parsed = SymbolTable::parse(&String::from_utf8(out).unwrap()).unwrap();
+
+        assert_eq!(parsed.address("MAIN"), Some(0x3000));
+        assert_eq!(parsed.address("DATA"), Some(0x3010));
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::{self, Read, Write},
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Once, OnceLock,
+    },
+};
+
+use libc::{c_int, SIGCONT, SIGINT, SIGTERM, SIGTSTP, SIG_DFL};
+use termios::{
+    tcsetattr, Termios, ECHO, ICANON, IGNBRK, IGNCR, INLCR, ISTRIP, IXON, PARMRK, TCSANOW,
+};
+
+use crate::console::Console;
+
+pub const STDIN: i32 = libc::STDIN_FILENO;
+
+/// The settings stdin had before raw mode, and the raw mode ones.
+struct Modes {
+    original: Termios,
+    raw: Termios,
+}
+
+/// Set once, before any handler that reads it is installed, so signal handlers only ever read
+/// it.
+static MODES: OnceLock<Modes> = OnceLock::new();
+/// Whether a [`RawMode`] is alive. A suspended process keeps it set to re-apply raw mode when
+/// it resumes.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static INSTALL_HANDLERS: Once = Once::new();
+/// Whether Ctrl-C raises [`INTERRUPT`] rather than ending the process.
+static CATCH_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+/// Set before `CATCH_INTERRUPTS` is first raised, so the handler only ever reads it.
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Puts the terminal in raw mode, without line buffering or echo, until dropped.
+///
+/// The original settings also come back when the process panics, exits, or is interrupted,
+/// terminated or suspended by a signal, so the shell is never left without echo. Resuming
+/// a suspended process puts raw mode back.
+pub struct RawMode(());
+
+impl RawMode {
+    /// Switches stdin to raw mode, or returns `None` when stdin is not a terminal and there
+    /// is nothing to switch.
+    pub fn enable() -> Result<Option<RawMode>, String> {
+        // SAFETY: isatty only inspects the descriptor.
+        if unsafe { libc::isatty(STDIN) } != 1 {
+            return Ok(None);
+        }
+
+        let original = Termios::from_fd(STDIN)
+            .map_err(|e| format!("error reading terminal settings: {}", e))?;
+        let mut raw = original;
+        // Keys reach the program one by one, unechoed and unaltered. ICRNL stays on so that
+        // Enter reads as the newline LC-3 programs expect, and ISIG so that Ctrl-C still
+        // interrupts.
+        raw.c_iflag &= !(IGNBRK | PARMRK | ISTRIP | INLCR | IGNCR | IXON);
+        raw.c_lflag &= !(ICANON | ECHO);
+        let modes = MODES.get_or_init(|| Modes { original, raw });
+
+        INSTALL_HANDLERS.call_once(install_handlers);
+
+        tcsetattr(STDIN, TCSANOW, &modes.raw)
+            .map_err(|e| format!("error setting terminal to raw mode: {}", e))?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        println!("terminal settings modified");
+
+        Ok(Some(RawMode(())))
+    }
+
+    /// Makes Ctrl-C raise the returned flag, for [`Vm::set_interrupt`](crate::vm::Vm::set_interrupt),
+    /// instead of ending the process while raw mode is on. Pressing it again before the flag
+    /// is lowered still ends the process, e.g. while the program waits for a key.
+    pub fn catch_interrupts(&self) -> Arc<AtomicBool> {
+        let interrupt = INTERRUPT.get_or_init(|| Arc::new(AtomicBool::new(false)));
+        CATCH_INTERRUPTS.store(true, Ordering::SeqCst);
+
+        Arc::clone(interrupt)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore();
+        ACTIVE.store(false, Ordering::SeqCst);
+        CATCH_INTERRUPTS.store(false, Ordering::SeqCst);
+        println!("terminal settings restored");
+    }
+}
+
+/// Puts back the original settings if raw mode is on. Only calls async-signal-safe functions.
+fn restore() {
+    if let (true, Some(modes)) = (ACTIVE.load(Ordering::SeqCst), MODES.get()) {
+        let _ = tcsetattr(STDIN, TCSANOW, &modes.original);
+    }
+}
+
+fn install_handlers() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        previous(info);
+    }));
+
+    extern "C" fn at_exit() {
+        restore();
+    }
+
+    // SAFETY: the handlers only read MODES and ACTIVE and call async-signal-safe functions.
+    unsafe {
+        libc::atexit(at_exit);
+        for signal in [SIGINT, SIGTERM, SIGTSTP, SIGCONT] {
+            set_handler(signal, on_signal as *const () as libc::sighandler_t);
+        }
+    }
+}
+
+/// Installs `handler` for `signal`, with the signal blocked while it runs.
+unsafe fn set_handler(signal: c_int, handler: libc::sighandler_t) {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = handler;
+    action.sa_flags = libc::SA_RESTART;
+    libc::sigemptyset(&mut action.sa_mask);
+    libc::sigaction(signal, &action, std::ptr::null_mut());
+}
+
+extern "C" fn on_signal(signal: c_int) {
+    if signal == SIGCONT {
+        // Back from a suspension: raw mode again, and ready to be suspended once more.
+        if let (true, Some(modes)) = (ACTIVE.load(Ordering::SeqCst), MODES.get()) {
+            let _ = tcsetattr(STDIN, TCSANOW, &modes.raw);
+        }
+        // SAFETY: see install_handlers.
+        unsafe { set_handler(SIGTSTP, on_signal as *const () as libc::sighandler_t) };
+        return;
+    }
+
+    if signal == SIGINT
+        && CATCH_INTERRUPTS.load(Ordering::SeqCst)
+        && INTERRUPT
+            .get()
+            .is_some_and(|interrupt| !interrupt.swap(true, Ordering::SeqCst))
+    {
+        return;
+    }
+
+    restore();
+    // Let the default action end or stop the process. The signal is blocked until this
+    // handler returns, which is when it takes effect.
+    // SAFETY: signal and raise are async-signal-safe.
+    unsafe {
+        libc::signal(signal, SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// The terminal the VM runs in, through stdin and stdout.
+#[derive(Debug, Default)]
+pub struct Terminal;
+
+impl Console for Terminal {
+    fn read_key(&mut self) -> Option<u8> {
+        let mut buffer = [0; 1];
+        io::stdin().read_exact(&mut buffer).ok()?;
+
+        Some(buffer[0])
+    }
+
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+    }
+
+    fn flush(&mut self) {
+        io::stdout().flush().expect("failed to flush");
+    }
+}
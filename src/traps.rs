@@ -1,4 +1,7 @@
-#[derive(PartialEq, Debug)]
+#[cfg(feature = "alloc")]
+use alloc::string::{String, ToString};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum TrapCode {
     GETC = 0x20,
     OUT = 0x21,
@@ -8,18 +11,26 @@ pub enum TrapCode {
     HALT = 0x25,
 }
 
+impl TrapCode {
+    /// The trap whose vector is the low byte of `bits`, if it has a service routine.
+    pub fn from_bits(bits: u16) -> Option<Self> {
+        match bits & 0xff {
+            0x20 => Some(Self::GETC),
+            0x21 => Some(Self::OUT),
+            0x22 => Some(Self::PUTS),
+            0x23 => Some(Self::IN),
+            0x24 => Some(Self::PUTSP),
+            0x25 => Some(Self::HALT),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
 impl TryFrom<u16> for TrapCode {
     type Error = String;
 
     fn try_from(bits: u16) -> Result<Self, Self::Error> {
-        match bits & 0xff {
-            0x20 => Ok(Self::GETC),
-            0x21 => Ok(Self::OUT),
-            0x22 => Ok(Self::PUTS),
-            0x23 => Ok(Self::IN),
-            0x24 => Ok(Self::PUTSP),
-            0x25 => Ok(Self::HALT),
-            _ => Err("Bad trapcode".to_string()),
-        }
+        Self::from_bits(bits).ok_or_else(|| "Bad trapcode".to_string())
     }
 }
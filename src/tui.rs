@@ -0,0 +1,665 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    io, mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    console::Console,
+    instructions::Instruction,
+    registers::{CondFlag, Register},
+    symbols::{format_address, SymbolTable},
+    vm::{Call, Stop, Vm},
+};
+
+/// How often the screen is redrawn while the program runs.
+const REFRESH: Duration = Duration::from_millis(50);
+/// Guest output kept for the output pane, in bytes.
+const OUTPUT_LIMIT: usize = 64 * 1024;
+const WORDS_PER_LINE: u16 = 8;
+/// How far PgUp and PgDn scroll the memory pane.
+const MEMORY_PAGE: u16 = 16 * WORDS_PER_LINE;
+
+const STOPPED_HELP: &str =
+    "s step  c continue  up/down move  b breakpoint  m memory here  PgUp/PgDn memory  . PC  q quit";
+const RUNNING_HELP: &str = "keys go to the program  Ctrl-C break";
+
+const GENERAL: [Register; 8] = [
+    Register::R0,
+    Register::R1,
+    Register::R2,
+    Register::R3,
+    Register::R4,
+    Register::R5,
+    Register::R6,
+    Register::R7,
+];
+
+/// Debugs `vm` full screen, with panes for the registers, the code around the PC, memory,
+/// the call stack and the program's output. Returns whether the program halted, rather
+/// than being quit.
+pub fn run(vm: &mut Vm) -> Result<bool, String> {
+    let terminal =
+        ratatui::try_init().map_err(|e| format!("error setting up the terminal: {}", e))?;
+    let screen = Rc::new(RefCell::new(Screen {
+        terminal,
+        debugger: Debugger::new(vm),
+    }));
+    // Raised every REFRESH, so that resuming returns to redraw the screen.
+    let interrupt = Arc::new(AtomicBool::new(false));
+    vm.track_calls();
+    vm.set_interrupt(Arc::clone(&interrupt));
+    vm.set_console(Box::new(GuestConsole(screen.clone())));
+
+    let done = AtomicBool::new(false);
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                thread::sleep(REFRESH);
+                interrupt.store(true, Ordering::SeqCst);
+            }
+        });
+        let result = debug(vm, &screen);
+        done.store(true, Ordering::SeqCst);
+        result
+    });
+    ratatui::restore();
+
+    result
+}
+
+/// What to do with the VM, from a key pressed while it is stopped.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Step,
+    Continue,
+    ToggleBreakpoint(u16),
+    Quit,
+}
+
+fn debug(vm: &mut Vm, screen: &RefCell<Screen>) -> Result<bool, String> {
+    // Set once the program halts or fails; it can still be inspected until quit.
+    let mut finished: Option<Result<(), String>> = None;
+    let mut status = format!(
+        "stopped at {}",
+        format_address(vm.symbols(), vm.registers().get(Register::PC))
+    );
+
+    loop {
+        let command = {
+            let mut screen = screen.borrow_mut();
+            screen.debugger.stop(vm, status.clone());
+            loop {
+                screen.draw()?;
+                let event = event::read().map_err(|e| e.to_string())?;
+                if let Some(command) = screen.debugger.handle_stopped_event(event) {
+                    break command;
+                }
+            }
+        };
+
+        let result = match command {
+            Command::Quit => {
+                return match finished {
+                    Some(result) => result.map(|()| true),
+                    None => Ok(false),
+                }
+            }
+            Command::ToggleBreakpoint(addr) => {
+                if !vm.remove_breakpoint(addr) {
+                    vm.add_breakpoint(addr);
+                }
+                screen.borrow_mut().debugger.refresh(vm);
+                continue;
+            }
+            _ if finished.is_some() => {
+                status = String::from("the program has finished, q to quit");
+                continue;
+            }
+            Command::Step => vm.step().map(|running| (!running).then_some(Stop::Halted)),
+            Command::Continue => continue_running(vm, screen).map(Some),
+        };
+
+        let pc = format_address(vm.symbols(), vm.registers().get(Register::PC));
+        status = match result {
+            Ok(None) => format!("stepped to {}", pc),
+            Ok(Some(Stop::Halted)) => {
+                finished = Some(Ok(()));
+                String::from("halted, q to quit")
+            }
+            Ok(Some(stop)) => format!("{} at {}", stop, pc),
+            // Ctrl-C twice while the program waited for a key ended its input.
+            Err(_) if screen.borrow().debugger.quit => return Ok(false),
+            Err(e) => {
+                let status = format!("error: {}", e);
+                finished = Some(Err(e));
+                status
+            }
+        };
+    }
+}
+
+/// Resumes `vm` until it stops for a reason other than redrawing the screen.
+fn continue_running(vm: &mut Vm, screen: &RefCell<Screen>) -> Result<Stop, String> {
+    screen.borrow_mut().debugger.start();
+
+    loop {
+        let stop = vm.resume()?;
+
+        let mut screen = screen.borrow_mut();
+        if stop != Stop::Interrupted || mem::take(&mut screen.debugger.break_requested) {
+            return Ok(stop);
+        }
+
+        screen.debugger.refresh(vm);
+        screen.draw()?;
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            let event = event::read().map_err(|e| e.to_string())?;
+            screen.debugger.handle_running_event(event);
+        }
+        if mem::take(&mut screen.debugger.break_requested) {
+            return Ok(Stop::Interrupted);
+        }
+    }
+}
+
+/// The terminal and what it shows, shared by the debugger and the guest console, which
+/// redraws it while the program waits for a key.
+struct Screen {
+    terminal: DefaultTerminal,
+    debugger: Debugger,
+}
+
+impl Screen {
+    fn draw(&mut self) -> Result<(), String> {
+        let debugger = &self.debugger;
+        self.terminal
+            .draw(|frame| debugger.draw(frame))
+            .map(|_| ())
+            .map_err(|e: io::Error| format!("error drawing the screen: {}", e))
+    }
+}
+
+/// The program's console: output goes to the output pane, and keys come from those typed
+/// while it runs.
+struct GuestConsole(Rc<RefCell<Screen>>);
+
+impl Console for GuestConsole {
+    fn read_key(&mut self) -> Option<u8> {
+        let mut screen = self.0.borrow_mut();
+
+        loop {
+            if let Some(key) = screen.debugger.keys.pop_front() {
+                return Some(key);
+            }
+            if screen.debugger.quit {
+                return None;
+            }
+
+            screen.debugger.status = String::from(if screen.debugger.break_requested {
+                "waiting for a key, then breaking; Ctrl-C again to quit"
+            } else {
+                "waiting for a key"
+            });
+            screen.draw().ok()?;
+            let event = event::read().ok()?;
+            screen.debugger.handle_running_event(event);
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.0.borrow_mut().debugger.write_output(text);
+    }
+
+    fn flush(&mut self) {}
+}
+
+/// The VM state the panes show, copied from the VM whenever it stops or the screen is
+/// redrawn while it runs.
+#[derive(Default)]
+struct Snapshot {
+    registers: [u16; 8],
+    pc: u16,
+    psr: u16,
+    cond: u16,
+    memory: Vec<u16>,
+    breakpoints: BTreeSet<u16>,
+    calls: Vec<Call>,
+    instructions: u64,
+}
+
+impl Snapshot {
+    fn of(vm: &Vm) -> Self {
+        let registers = vm.registers();
+
+        Self {
+            registers: GENERAL.map(|register| registers.get(register)),
+            pc: registers.get(Register::PC),
+            psr: registers.psr(),
+            cond: registers.get(Register::COND),
+            memory: (0..=u16::MAX).map(|addr| vm.memory().peek(addr)).collect(),
+            breakpoints: vm.breakpoints().collect(),
+            calls: vm.call_stack().to_vec(),
+            instructions: vm.instructions(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Debugger {
+    snapshot: Snapshot,
+    symbols: Option<SymbolTable>,
+    /// The address selected in the code pane.
+    cursor: u16,
+    memory_start: u16,
+    running: bool,
+    status: String,
+    output: String,
+    /// Inside an ANSI escape sequence, which the output pane leaves out.
+    in_escape: bool,
+    /// Keys typed for the program that it has not read yet.
+    keys: VecDeque<u8>,
+    /// Ctrl-C was pressed while the program ran.
+    break_requested: bool,
+    /// Ctrl-C was pressed again before the program stopped.
+    quit: bool,
+}
+
+impl Debugger {
+    fn new(vm: &Vm) -> Self {
+        let pc = vm.registers().get(Register::PC);
+
+        Self {
+            symbols: vm.symbols().cloned(),
+            memory_start: pc & !(WORDS_PER_LINE - 1),
+            ..Self::default()
+        }
+    }
+
+    fn refresh(&mut self, vm: &Vm) {
+        self.snapshot = Snapshot::of(vm);
+    }
+
+    fn stop(&mut self, vm: &Vm, status: String) {
+        self.refresh(vm);
+        self.cursor = self.snapshot.pc;
+        self.running = false;
+        self.status = status;
+    }
+
+    fn start(&mut self) {
+        self.running = true;
+        self.break_requested = false;
+        self.status = String::from("running");
+    }
+
+    fn handle_stopped_event(&mut self, event: Event) -> Option<Command> {
+        let key = pressed(event)?;
+        if is_ctrl_c(key) {
+            return Some(Command::Quit);
+        }
+
+        match key.code {
+            KeyCode::Char('s') => return Some(Command::Step),
+            KeyCode::Char('c') => return Some(Command::Continue),
+            KeyCode::Char('q') => return Some(Command::Quit),
+            KeyCode::Char('b') => return Some(Command::ToggleBreakpoint(self.cursor)),
+            KeyCode::Char('m') => self.memory_start = self.cursor & !(WORDS_PER_LINE - 1),
+            KeyCode::Char('.') => self.cursor = self.snapshot.pc,
+            KeyCode::Up => self.cursor = self.cursor.wrapping_sub(1),
+            KeyCode::Down => self.cursor = self.cursor.wrapping_add(1),
+            KeyCode::PageUp => self.memory_start = self.memory_start.wrapping_sub(MEMORY_PAGE),
+            KeyCode::PageDown => self.memory_start = self.memory_start.wrapping_add(MEMORY_PAGE),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Queues keys for the program, or asks to break on Ctrl-C.
+    fn handle_running_event(&mut self, event: Event) {
+        let Some(key) = pressed(event) else {
+            return;
+        };
+
+        if is_ctrl_c(key) {
+            self.quit = mem::replace(&mut self.break_requested, true);
+        } else if let Some(key) = guest_key(key) {
+            self.keys.push_back(key);
+        }
+    }
+
+    fn write_output(&mut self, text: &str) {
+        for c in text.chars() {
+            if self.in_escape {
+                self.in_escape = !c.is_ascii_alphabetic();
+            } else if c == '\x1b' {
+                self.in_escape = true;
+            } else if c == '\n' || !c.is_control() {
+                self.output.push(c);
+            }
+        }
+
+        if self.output.len() > OUTPUT_LIMIT {
+            let mut start = self.output.len() - OUTPUT_LIMIT;
+            while !self.output.is_char_boundary(start) {
+                start += 1;
+            }
+            self.output.drain(..start);
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [top, middle, output, status] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [registers, calls] =
+            Layout::horizontal([Constraint::Length(42), Constraint::Fill(1)]).areas(top);
+        let [code, memory] =
+            Layout::horizontal([Constraint::Fill(1), Constraint::Length(66)]).areas(middle);
+
+        self.draw_registers(frame, registers);
+        self.draw_calls(frame, calls);
+        self.draw_code(frame, code);
+        self.draw_memory(frame, memory);
+        self.draw_output(frame, output);
+
+        let help = if self.running {
+            RUNNING_HELP
+        } else {
+            STOPPED_HELP
+        };
+        frame.render_widget(
+            Paragraph::new(format!("{}  |  {}", self.status, help))
+                .style(Style::new().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let snapshot = &self.snapshot;
+        let registers = |range: std::ops::Range<usize>| {
+            let values: Vec<String> = range
+                .map(|index| format!("{} x{:04X}", GENERAL[index], snapshot.registers[index]))
+                .collect();
+            Line::from(values.join("  "))
+        };
+        let flag = |set: CondFlag, c: char| {
+            if snapshot.cond == set as u16 {
+                c
+            } else {
+                '-'
+            }
+        };
+
+        let lines = vec![
+            registers(0..4),
+            registers(4..8),
+            Line::from(format!(
+                "PC x{:04X}  PSR x{:04X}  CC {}{}{}",
+                snapshot.pc,
+                snapshot.psr,
+                flag(CondFlag::NEG, 'n'),
+                flag(CondFlag::ZRO, 'z'),
+                flag(CondFlag::POS, 'p'),
+            )),
+            Line::from(format!("{} instructions", snapshot.instructions)),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Registers")),
+            area,
+        );
+    }
+
+    fn draw_calls(&self, frame: &mut Frame, area: Rect) {
+        let symbols = self.symbols.as_ref();
+        let lines: Vec<Line> = if self.snapshot.calls.is_empty() {
+            vec![Line::from("no calls")]
+        } else {
+            self.snapshot
+                .calls
+                .iter()
+                .rev()
+                .map(|call| {
+                    Line::from(format!(
+                        "{:<12} from {}",
+                        format_address(symbols, call.entry),
+                        format_address(symbols, call.return_addr.wrapping_sub(1))
+                    ))
+                })
+                .collect()
+        };
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Call stack")),
+            area,
+        );
+    }
+
+    /// Disassembles around the cursor, marking the PC with `>` and breakpoints with `*`.
+    fn draw_code(&self, frame: &mut Frame, area: Rect) {
+        let symbols = self.symbols.as_ref();
+        let rows = area.height.saturating_sub(2);
+        let start = self.cursor.wrapping_sub(rows / 2);
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|offset| {
+                let addr = start.wrapping_add(offset);
+                let bits = self.snapshot.memory[addr as usize];
+                let text = match Instruction::try_from(bits) {
+                    Ok(instruction) => instruction.at(addr, symbols).to_string(),
+                    Err(_) => format!(".FILL x{:04X}", bits),
+                };
+                let line = Line::from(format!(
+                    "{}{} {:<12} x{:04X}  {}",
+                    if self.snapshot.breakpoints.contains(&addr) {
+                        '*'
+                    } else {
+                        ' '
+                    },
+                    if addr == self.snapshot.pc { '>' } else { ' ' },
+                    format_address(symbols, addr),
+                    bits,
+                    text
+                ));
+
+                let mut style = Style::new();
+                if addr == self.snapshot.pc {
+                    style = style.add_modifier(Modifier::BOLD);
+                }
+                if addr == self.cursor && !self.running {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                line.style(style)
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Code")),
+            area,
+        );
+    }
+
+    /// Words in hex, with the ones that are printable characters alongside.
+    fn draw_memory(&self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2);
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| {
+                let start = self
+                    .memory_start
+                    .wrapping_add(row.wrapping_mul(WORDS_PER_LINE));
+                let words: Vec<u16> = (0..WORDS_PER_LINE)
+                    .map(|offset| self.snapshot.memory[start.wrapping_add(offset) as usize])
+                    .collect();
+                let hex: Vec<String> = words.iter().map(|word| format!("x{:04X}", word)).collect();
+                let text: String = words
+                    .iter()
+                    .map(|&word| match char::from_u32(word as u32) {
+                        Some(c) if c.is_ascii_graphic() || c == ' ' => c,
+                        _ => '.',
+                    })
+                    .collect();
+
+                Line::from(format!("x{:04X}  {}  {}", start, hex.join(" "), text))
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Memory")),
+            area,
+        );
+    }
+
+    fn draw_output(&self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let lines: Vec<&str> = self.output.split('\n').collect();
+        let lines: Vec<Line> = lines[lines.len().saturating_sub(rows)..]
+            .iter()
+            .map(|&line| Line::from(line))
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Output")),
+            area,
+        );
+    }
+}
+
+fn pressed(event: Event) -> Option<KeyEvent> {
+    match event {
+        Event::Key(key) if key.kind == KeyEventKind::Press => Some(key),
+        _ => None,
+    }
+}
+
+fn is_ctrl_c(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+/// The byte a terminal would send the program for `key`.
+fn guest_key(key: KeyEvent) -> Option<u8> {
+    match key.code {
+        KeyCode::Char(c) if c.is_ascii() && key.modifiers.contains(KeyModifiers::CONTROL) => {
+            Some(c as u8 & 0x1F)
+        }
+        KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+        KeyCode::Enter => Some(b'\n'),
+        KeyCode::Tab => Some(b'\t'),
+        KeyCode::Backspace => Some(0x08),
+        KeyCode::Esc => Some(0x1B),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, registers::Registers};
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Event {
+        Event::Key(KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn test_draw_panes() {
+        let assembly = assemble(
+            "
+            .ORIG x3000
+            MAIN LEA R0, TEXT
+            JSR PRINT
+            HALT
+            PRINT PUTS
+            RET
+            TEXT .STRINGZ \"hi\"
+            .END
+            ",
+        )
+        .unwrap();
+        let mut memory = Memory::default();
+        memory.load(&assembly.image);
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_symbols(assembly.symbols);
+        vm.track_calls();
+        vm.add_breakpoint(0x3004);
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        let mut debugger = Debugger::new(&vm);
+        debugger.memory_start = 0x3000;
+        debugger.stop(&vm, String::from("stepped"));
+        debugger.write_output("\x1b[2Jhello\r\nworld");
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| debugger.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let text: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect()
+            })
+            .collect();
+        let text = text.join("\n");
+
+        assert!(text.contains("R0 x3005  R1 x0000"));
+        assert!(text.contains("PC x3003  PSR x0002  CC -z-"));
+        assert!(text.contains("PRINT        from MAIN+1"));
+        assert!(text.contains(" > PRINT        xF022  TRAP x22"));
+        assert!(text.contains("*  PRINT+1      xC1C0  RET"));
+        assert!(text.contains("x3000  xE004 x4801 xF025 xF022 xC1C0 x0068 x0069 x0000  .....hi."));
+        assert!(text.contains("│hello"));
+        assert!(text.contains("│world"));
+        assert!(text.contains("stepped  |  s step"));
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut debugger = Debugger {
+            cursor: 0x3000,
+            ..Debugger::default()
+        };
+
+        assert_eq!(
+            debugger.handle_stopped_event(key(KeyCode::Down, KeyModifiers::NONE)),
+            None
+        );
+        assert_eq!(
+            debugger.handle_stopped_event(key(KeyCode::Char('b'), KeyModifiers::NONE)),
+            Some(Command::ToggleBreakpoint(0x3001))
+        );
+
+        debugger.start();
+        debugger.handle_running_event(key(KeyCode::Char('w'), KeyModifiers::NONE));
+        debugger.handle_running_event(key(KeyCode::Enter, KeyModifiers::NONE));
+        debugger.handle_running_event(key(KeyCode::Char('d'), KeyModifiers::CONTROL));
+        assert_eq!(debugger.keys, [b'w', b'\n', 0x04]);
+
+        debugger.handle_running_event(key(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert!(debugger.break_requested && !debugger.quit);
+        debugger.handle_running_event(key(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert!(debugger.quit);
+    }
+}
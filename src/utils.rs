@@ -1,4 +1,5 @@
-use std::io::Read;
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String};
 
 use crate::{memory::Memory, registers::MemoryMappedReg};
 
@@ -9,15 +10,61 @@ pub fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
     x
 }
 
-pub fn handle_keyboard(memory: &mut Memory) {
-    let mut buffer = [0; 1];
-    std::io::stdin().read_exact(&mut buffer).unwrap();
+/// Checks that `value` fits in a `bit_count`-bit two's complement field and returns it
+/// sign-extended to 16 bits, the way [`sign_extend`] leaves decoded fields.
+#[cfg(feature = "alloc")]
+pub fn fit_signed(value: i32, bit_count: u8) -> Result<u16, String> {
+    let min = -(1 << (bit_count - 1));
+    let max = (1 << (bit_count - 1)) - 1;
+
+    if value < min || value > max {
+        return Err(format!("{} does not fit in {} bits", value, bit_count));
+    }
+
+    Ok(value as u16)
+}
 
-    if buffer[0] != 0 {
-        memory.write(MemoryMappedReg::Kbsr as u16, 1 << 15);
-        memory.write(MemoryMappedReg::Kbdr as u16, buffer[0] as u16);
+/// Parses `#10`, `#-3`, `x3000`, `0x3000`, `b101` and bare decimal literals.
+pub fn parse_number(word: &str) -> Option<i32> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+
+    let (radix, digits) = if let Some(rest) = digits.strip_prefix('#') {
+        (10, rest)
+    } else if let Some(rest) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        (16, rest)
+    } else if let Some(rest) = digits.strip_prefix(['x', 'X']) {
+        (16, rest)
+    } else if let Some(rest) = digits.strip_prefix(['b', 'B']) {
+        (2, rest)
     } else {
-        memory.write(MemoryMappedReg::Kbsr as u16, 0)
+        (10, digits)
+    };
+
+    // `#-3` carries its sign after the prefix.
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) if !negative => (true, rest),
+        _ => (negative, digits),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    let value = i32::from_str_radix(digits, radix).ok()?;
+
+    Some(if negative { -value } else { value })
+}
+
+pub fn handle_keyboard(memory: &mut Memory) {
+    match memory.read_key() {
+        Some(key) if key != 0 => {
+            memory.write(MemoryMappedReg::Kbsr as u16, 1 << 15);
+            memory.write(MemoryMappedReg::Kbdr as u16, key as u16);
+        }
+        _ => memory.write(MemoryMappedReg::Kbsr as u16, 0),
     }
 }
 
@@ -36,4 +83,24 @@ mod tests {
         assert_eq!(sign_extend(0b0000_1001, 4), 0b1111_1111_1111_1001);
         assert_eq!(sign_extend(0b0000_0110, 3), 0b1111_1111_1111_1110);
     }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_fit_signed() {
+        assert_eq!(fit_signed(15, 5), Ok(0b0000_0000_0000_1111));
+        assert_eq!(fit_signed(-16, 5), Ok(0b1111_1111_1111_0000));
+        assert!(fit_signed(16, 5).is_err());
+        assert!(fit_signed(-17, 5).is_err());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("#10"), Some(10));
+        assert_eq!(parse_number("#-3"), Some(-3));
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("0xFF"), Some(0xFF));
+        assert_eq!(parse_number("b101"), Some(5));
+        assert_eq!(parse_number("-7"), Some(-7));
+        assert_eq!(parse_number("LOOP"), None);
+    }
 }
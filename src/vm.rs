@@ -1,47 +1,589 @@
+use alloc::{boxed::Box, collections::BTreeSet, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt, iter, mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+#[cfg(feature = "std")]
+use serde_json::Value;
+
 use crate::{
+    console::Console,
+    engine::{Block, BlockCache, Engine, Outcome},
     instructions::Instruction,
     memory::Memory,
+    protection::{is_privileged, Access, ProtectionMap},
     registers::{CondFlag, Register, Registers},
+    symbols::{format_address, SymbolTable},
 };
+#[cfg(feature = "std")]
+use crate::{coverage::Coverage, flamegraph, profile::Profile};
 
 const PC_START: u16 = 0x3000;
+/// A return address for [`Vm::call`]: the last word before the device registers, which
+/// programs do not run.
+pub const RETURN_SENTINEL: u16 = 0xFDFF;
+
+/// Why [`Vm::resume`] returned. Unless halted, the PC is at the next instruction to run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The program reached HALT.
+    Halted,
+    /// The [interrupt flag](Vm::set_interrupt) was raised.
+    Interrupted,
+    /// The PC reached a breakpoint.
+    Breakpoint(u16),
+    /// The subroutine started by [`Vm::call`] returned.
+    Returned,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Halted => write!(f, "halted"),
+            Stop::Interrupted => write!(f, "interrupted"),
+            Stop::Breakpoint(_) => write!(f, "breakpoint"),
+            Stop::Returned => write!(f, "returned"),
+        }
+    }
+}
+
+/// A subroutine call, by `JSR` or `JSRR`, that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    pub entry: u16,
+    pub return_addr: u16,
+}
 
 pub struct Vm {
     registers: Registers,
     memory: Memory,
+    symbols: Option<SymbolTable>,
+    #[cfg(feature = "std")]
+    trace: Option<Box<dyn Write>>,
+    #[cfg(feature = "std")]
+    profile: Option<Profile>,
+    #[cfg(feature = "std")]
+    coverage: Option<Coverage>,
+    engine: Engine,
+    blocks: BlockCache,
+    instructions: u64,
+    instruction_limit: Option<u64>,
+    interrupt: Option<Arc<AtomicBool>>,
+    breakpoints: BTreeSet<u16>,
+    calls: Option<Vec<Call>>,
+    protection: ProtectionMap,
+    /// Set when stopped at a breakpoint, so that resuming runs the instruction there.
+    at_breakpoint: bool,
 }
 
 impl Vm {
+    /// A VM that starts at the origin of the first image loaded into `memory`, or at
+    /// x3000 if there is none.
     pub fn new(mut registers: Registers, memory: Memory) -> Self {
-        registers.set(Register::PC, PC_START);
+        registers.set(Register::PC, memory.entry().unwrap_or(PC_START));
         registers.set(Register::COND, CondFlag::ZRO as u16);
 
-        Self { registers, memory }
+        Self {
+            registers,
+            memory,
+            symbols: None,
+            #[cfg(feature = "std")]
+            trace: None,
+            #[cfg(feature = "std")]
+            profile: None,
+            #[cfg(feature = "std")]
+            coverage: None,
+            engine: Engine::default(),
+            blocks: BlockCache::default(),
+            instructions: 0,
+            instruction_limit: None,
+            interrupt: None,
+            breakpoints: BTreeSet::new(),
+            calls: None,
+            protection: ProtectionMap::default(),
+            at_breakpoint: false,
+        }
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.registers.set(Register::PC, pc);
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        self.registers.set(register, value);
+    }
+
+    pub fn set_psr(&mut self, psr: u16) -> Result<(), String> {
+        self.registers.set_psr(psr)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Where keys are read from and output goes, see [`Memory::set_console`].
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.memory.set_console(console);
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// Makes [`Vm::run`] fail, before executing it, on the instruction past `limit`.
+    pub fn set_instruction_limit(&mut self, limit: u64) {
+        self.instruction_limit = Some(limit);
+    }
+
+    /// How many instructions have executed, HALT included.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Labels used to show addresses in traces and error messages.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    /// Makes [`Vm::resume`] stop at the next instruction boundary once `flag` is raised,
+    /// e.g. from a signal handler or another thread. The flag is lowered again when it stops.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Makes [`Vm::resume`] stop before executing the instruction at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Returns whether there was a breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Keeps the stack of subroutine calls from now on, for [`Vm::call_stack`].
+    pub fn track_calls(&mut self) {
+        self.calls.get_or_insert_with(Vec::new);
+    }
+
+    /// The calls the program is in, outermost first, if [tracked](Vm::track_calls).
+    pub fn call_stack(&self) -> &[Call] {
+        self.calls.as_deref().unwrap_or_default()
+    }
+
+    /// Fails instructions that run or touch memory the map protects. In user mode, the
+    /// system area and the device registers are off limits as well.
+    pub fn set_protection(&mut self, protection: ProtectionMap) {
+        self.protection = protection;
+    }
+
+    /// Writes every executed instruction, with its address, to `trace`.
+    #[cfg(feature = "std")]
+    pub fn set_trace(&mut self, trace: Box<dyn Write>) {
+        self.trace = Some(trace);
+    }
+
+    /// Counts executed instructions, memory accesses and subroutine calls from now on.
+    #[cfg(feature = "std")]
+    pub fn enable_profile(&mut self) {
+        if self.profile.is_none() {
+            self.set_profile(Profile::default());
+        }
+    }
+
+    /// Like [`Vm::enable_profile`], starting from `profile`, e.g. one that keeps spans.
+    #[cfg(feature = "std")]
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+        self.memory.count_accesses();
+    }
+
+    #[cfg(feature = "std")]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Writes the profile report, if profiling is enabled.
+    #[cfg(feature = "std")]
+    pub fn write_profile(&self, out: &mut impl Write) -> io::Result<()> {
+        match &self.profile {
+            Some(profile) => profile.write_report(out, &self.memory, self.symbols.as_ref()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn profile_json(&self) -> Option<Value> {
+        self.profile
+            .as_ref()
+            .map(|profile| profile.to_json(&self.memory, self.symbols.as_ref()))
+    }
+
+    /// Writes the profiled call stacks in the folded flame graph format.
+    #[cfg(feature = "std")]
+    pub fn write_folded_stacks(&self, out: &mut impl Write) -> io::Result<()> {
+        match &self.profile {
+            Some(profile) => flamegraph::write_folded(out, profile, self.symbols.as_ref()),
+            None => Ok(()),
+        }
+    }
+
+    /// The profiled calls as Chrome trace events, if the profile keeps spans.
+    #[cfg(feature = "std")]
+    pub fn chrome_trace(&self) -> Option<Value> {
+        flamegraph::chrome_trace(self.profile.as_ref()?, self.symbols.as_ref())
+    }
+
+    /// Records executed addresses and branch outcomes from now on.
+    #[cfg(feature = "std")]
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
     }
 
     fn fetch_next_instruction(&mut self) -> u16 {
         let mar = self.registers.get(Register::PC);
         self.registers.program_counter_increment();
 
-        self.memory.read(mar)
+        self.memory.fetch(mar)
+    }
+
+    /// Runs until HALT. Stopping anywhere else, at a breakpoint or when interrupted, is an
+    /// error.
+    pub fn run(&mut self) -> Result<(), String> {
+        match self.resume()? {
+            Stop::Halted => Ok(()),
+            stop => Err(format!(
+                "{} at {}",
+                stop,
+                format_address(self.symbols.as_ref(), self.registers.get(Register::PC))
+            )),
+        }
+    }
+
+    /// Runs until HALT, a breakpoint or an interrupt. Resuming at a breakpoint runs the
+    /// instruction there rather than stopping again.
+    pub fn resume(&mut self) -> Result<Stop, String> {
+        let result = match self.engine {
+            Engine::Interpreter => self.interpret(),
+            Engine::Blocks => self.run_blocks(),
+        };
+        self.memory.console().flush();
+
+        #[cfg(feature = "std")]
+        if let Some(trace) = &mut self.trace {
+            trace
+                .flush()
+                .map_err(|e| format!("error writing trace: {}", e))?;
+        }
+
+        result
     }
 
-    pub fn run(&mut self) {
+    /// Calls the subroutine at `entry` as `JSR` would, with `return_addr` in R7, and runs
+    /// until it returns there. The return address should not otherwise be executed, so
+    /// [`RETURN_SENTINEL`] is a good choice. Stops at HALT, breakpoints and interrupts as
+    /// [`Vm::resume`] does.
+    pub fn call(&mut self, entry: u16, return_addr: u16) -> Result<Stop, String> {
+        self.registers.set(Register::R7, return_addr);
+        self.set_pc(entry);
+        self.at_breakpoint = false;
+
+        let temporary = self.breakpoints.insert(return_addr);
+        let result = self.resume();
+        if temporary {
+            self.breakpoints.remove(&return_addr);
+        }
+
+        match result? {
+            Stop::Breakpoint(addr) if addr == return_addr => {
+                self.at_breakpoint &= !temporary;
+                Ok(Stop::Returned)
+            }
+            stop => Ok(stop),
+        }
+    }
+
+    /// Fetches, decodes and executes one instruction, regardless of breakpoints. Returns
+    /// `false` at HALT.
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.at_breakpoint = false;
+
+        let pc = self.registers.get(Register::PC);
+        let bits = self.fetch_next_instruction();
+        let symbols = self.symbols.as_ref();
+        let instruction = self
+            .memory
+            .decode(pc)
+            .map_err(|e| format!("{} at {} (x{:04X})", e, format_address(symbols, pc), bits))?;
+
+        self.execute(pc, bits, &instruction)
+    }
+
+    /// Whether to stop before the instruction at `pc`.
+    fn stop_at(&mut self, pc: u16) -> Option<Stop> {
+        let resumed = mem::take(&mut self.at_breakpoint);
+
+        if self
+            .interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.swap(false, Ordering::SeqCst))
+        {
+            return Some(Stop::Interrupted);
+        }
+        if !resumed && !self.breakpoints.is_empty() && self.breakpoints.contains(&pc) {
+            self.at_breakpoint = true;
+            return Some(Stop::Breakpoint(pc));
+        }
+
+        None
+    }
+
+    fn interpret(&mut self) -> Result<Stop, String> {
         loop {
-            let instruction = Instruction::try_from(self.fetch_next_instruction()).unwrap();
+            if let Some(stop) = self.stop_at(self.registers.get(Register::PC)) {
+                return Ok(stop);
+            }
+            if !self.step()? {
+                return Ok(Stop::Halted);
+            }
+        }
+    }
 
-            if instruction.is_halt() {
-                break;
+    fn run_blocks(&mut self) -> Result<Stop, String> {
+        loop {
+            let pc = self.registers.get(Register::PC);
+            let Some(block) = self.blocks.get(pc, &mut self.memory) else {
+                if let Some(stop) = self.stop_at(pc) {
+                    return Ok(stop);
+                }
+                if !self.step()? {
+                    return Ok(Stop::Halted);
+                }
+                continue;
+            };
+
+            if self.runs_translated(&block) {
+                if let Some(stop) = self.stop_at(pc) {
+                    return Ok(stop);
+                }
+                let (executed, outcome) = block.run(&mut self.registers, &mut self.memory);
+                self.instructions += executed;
+                match outcome {
+                    Outcome::Continue => continue,
+                    Outcome::Halted => return Ok(Stop::Halted),
+                    Outcome::InputEnded(addr) => {
+                        return Err(format!(
+                            "input ended at {}",
+                            format_address(self.symbols.as_ref(), addr)
+                        ))
+                    }
+                }
             }
 
-            instruction.execute(&mut self.registers, &mut self.memory);
+            for (index, (bits, instruction)) in block.instructions.iter().enumerate() {
+                let addr = block.start.wrapping_add(index as u16);
+                if let Some(stop) = self.stop_at(addr) {
+                    self.registers.set(Register::PC, addr);
+                    return Ok(stop);
+                }
+                self.registers.set(Register::PC, addr.wrapping_add(1));
+
+                if !self.execute(addr, *bits, instruction)? {
+                    return Ok(Stop::Halted);
+                }
+                if !self.blocks.is_current(&self.memory) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Whether `block` can run translated: nothing needs to see its instructions one at a
+    /// time, and it cannot pass a breakpoint or the instruction limit part way through.
+    fn runs_translated(&self, block: &Block) -> bool {
+        #[cfg(feature = "std")]
+        if self.trace.is_some() || self.profile.is_some() || self.coverage.is_some() {
+            return false;
+        }
+
+        let len = block.instructions.len() as u64;
+        let last = block.start.wrapping_add(len as u16 - 1);
+
+        self.calls.is_none()
+            && self.protection.is_empty()
+            && !self.registers.is_user_mode()
+            && self
+                .instruction_limit
+                .is_none_or(|limit| limit - self.instructions >= len)
+            && self
+                .breakpoints
+                .range(block.start..=last)
+                .all(|&addr| addr == block.start)
+    }
+
+    /// Traces, profiles and executes `instruction`, fetched from `pc`, with the PC already
+    /// past it. Returns `false` at HALT, which is not executed.
+    fn execute(
+        &mut self,
+        pc: u16,
+        // Only the profile needs the raw word.
+        #[cfg_attr(not(feature = "std"), allow(unused_variables))] bits: u16,
+        instruction: &Instruction,
+    ) -> Result<bool, String> {
+        let symbols = self.symbols.as_ref();
+
+        if self.instruction_limit == Some(self.instructions) {
+            self.registers.set(Register::PC, pc);
+            return Err(format!(
+                "stopped after {} instructions at {}",
+                self.instructions,
+                format_address(symbols, pc)
+            ));
+        }
+        if self.registers.is_user_mode() || !self.protection.is_empty() {
+            if let Err(violation) = self.check_access(pc, instruction) {
+                self.registers.set(Register::PC, pc);
+                return Err(violation);
+            }
+        }
+        self.instructions += 1;
+
+        #[cfg(feature = "std")]
+        if let Some(trace) = &mut self.trace {
+            writeln!(
+                trace,
+                "{:<12} {}",
+                format_address(symbols, pc),
+                instruction.at(pc, symbols)
+            )
+            .map_err(|e| format!("error writing trace: {}", e))?;
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(profile) = &mut self.profile {
+            profile.record_execution(pc, bits);
+        }
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, instruction, &self.registers);
+        }
+
+        if instruction.is_halt() {
+            return Ok(false);
+        }
+
+        instruction.execute(&mut self.registers, &mut self.memory);
+        if self.memory.input_ended() {
+            return Err(format!("input ended at {}", format_address(symbols, pc)));
+        }
+
+        let next_pc = self.registers.get(Register::PC);
+        if let Some(calls) = &mut self.calls {
+            record_call(calls, pc, instruction, next_pc);
+        }
+        #[cfg(feature = "std")]
+        if let Some(profile) = &mut self.profile {
+            profile.record_control_flow(pc, instruction, next_pc);
+        }
+
+        Ok(true)
+    }
+
+    /// Fails if running `instruction` from `pc`, with the PC past it, would break the
+    /// privilege rules or the protection map.
+    fn check_access(&self, pc: u16, instruction: &Instruction) -> Result<(), String> {
+        let symbols = self.symbols.as_ref();
+        let accesses = iter::once((pc, Access::Execute))
+            .chain(instruction.accesses(&self.registers, &self.memory));
+
+        for (addr, access) in accesses {
+            let target = format_address(symbols, addr);
+            let (kind, reason) = if self.registers.is_user_mode() && is_privileged(addr) {
+                ("access control", format!("user-mode {} {}", access, target))
+            } else if let Some(protection) = self.protection.check(addr, access) {
+                (
+                    "protection",
+                    format!("{} {} {}", access, protection, target),
+                )
+            } else {
+                continue;
+            };
+
+            return Err(format!(
+                "{} violation at {} ({}): {}",
+                kind,
+                format_address(symbols, pc),
+                instruction.at(pc, symbols),
+                reason
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Pushes a call or pops the calls a return leaves, the way the profile counts them: a `RET`
+/// closes the innermost call that would return to `next_pc`, and one that matches no call is
+/// ignored.
+fn record_call(calls: &mut Vec<Call>, pc: u16, instruction: &Instruction, next_pc: u16) {
+    if instruction.is_call() {
+        calls.push(Call {
+            entry: next_pc,
+            return_addr: pc.wrapping_add(1),
+        });
+    } else if instruction.is_return() {
+        if let Some(depth) = calls.iter().rposition(|call| call.return_addr == next_pc) {
+            calls.truncate(depth);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::{string::ToString, vec};
+    #[cfg(feature = "std")]
+    use std::{cell::RefCell, io, rc::Rc};
+
     use super::*;
+    #[cfg(feature = "std")]
+    use crate::console::Scripted;
+    use crate::{image::Image, instructions::Add, protection::Protection};
+
+    /// A trace sink the test can still read after handing it to the VM.
+    #[cfg(feature = "std")]
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     fn test_instruction_cycle() {
@@ -49,12 +591,10 @@ mod tests {
         registers.set(Register::PC, 0x3000);
 
         let mut memory = Memory::default();
-        // ADD  R0, R0, 1
-        #[allow(clippy::unusual_byte_groupings)]
-        let i1 = 0b0001_000_000_1_00001;
+        let i1 = Instruction::Add(Add::immediate(Register::R0, Register::R0, 1).unwrap()).encode();
         memory.write(0x3000, i1);
-        // ADD  R0, R0, R0
-        let i2 = 0b0001_000_000_0_00_000;
+        let i2 = Instruction::Add(Add::register(Register::R0, Register::R0, Register::R0).unwrap())
+            .encode();
         memory.write(0x3001, i2);
 
         let mut vm = Vm::new(registers, memory);
@@ -73,4 +613,305 @@ mod tests {
         instruction.execute(&mut vm.registers, &mut vm.memory);
         assert_eq!(vm.registers.get(Register::R0), 2);
     }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_trace_uses_symbols() {
+        let mut memory = Memory::default();
+        // ADD  R0, R0, 1
+        memory.write(0x3000, 0b0001_000_000_1_00001);
+        // BRn  #0
+        memory.write(0x3001, 0b0000_100_000000000);
+        // HALT
+        memory.write(0x3002, 0xF025);
+        let mut symbols = SymbolTable::default();
+        symbols.insert("MAIN", 0x3000);
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        let trace = SharedBuffer::default();
+        vm.set_symbols(symbols);
+        vm.set_trace(Box::new(trace.clone()));
+
+        vm.run().unwrap();
+
+        assert_eq!(
+            String::from_utf8(trace.0.borrow().clone()).unwrap(),
+            "MAIN         ADD R0, R0, #1\n\
+             MAIN+1       BRn MAIN+2\n\
+             MAIN+2       TRAP x25\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_profile() {
+        let mut memory = Memory::default();
+        // JSR  #1
+        memory.write(0x3000, 0b0100_1_00000000001);
+        // HALT
+        memory.write(0x3001, 0xF025);
+        // STR  R0, R6, #0
+        memory.write(0x3002, 0b0111_000_110_000000);
+        // RET
+        memory.write(0x3003, 0b1100_000_111_000000);
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_register(Register::R6, 0x4000);
+        vm.enable_profile();
+        vm.run().unwrap();
+
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.instructions(), 4);
+        assert_eq!(profile.executions(0x3002), 1);
+        assert_eq!(profile.subroutines()[0].0, 0x3002);
+        assert_eq!(profile.subroutines()[0].1.inclusive, 2);
+
+        let json = vm.profile_json().unwrap();
+        assert_eq!(json["instructions"], 4);
+        assert_eq!(json["memory"][3]["region"], "user");
+        assert_eq!(json["memory"][3]["writes"], 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_scripted_console() {
+        let mut memory = Memory::default();
+        // GETC
+        memory.write(0x3000, 0xF020);
+        // OUT
+        memory.write(0x3001, 0xF021);
+        // BRnzp #-3
+        memory.write(0x3002, 0b0000_111_111111101);
+        let output = SharedBuffer::default();
+        memory.set_console(Box::new(Scripted::new("ok", output.clone())));
+
+        let mut vm = Vm::new(Registers::default(), memory);
+
+        assert_eq!(vm.run(), Err("input ended at x3000".to_string()));
+        assert_eq!(output.0.borrow().as_slice(), b"ok");
+        assert_eq!(vm.instructions(), 7);
+    }
+
+    #[test]
+    fn test_interrupts_and_breakpoints() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+
+        for engine in [Engine::Interpreter, Engine::Blocks] {
+            let mut memory = Memory::default();
+            // ADD  R0, R0, #1
+            memory.write(0x3000, 0b0001_000_000_1_00001);
+            // ADD  R0, R0, #1
+            memory.write(0x3001, 0b0001_000_000_1_00001);
+            // HALT
+            memory.write(0x3002, 0xF025);
+
+            let mut vm = Vm::new(Registers::default(), memory);
+            vm.set_engine(engine);
+            vm.set_interrupt(Arc::clone(&interrupt));
+            vm.add_breakpoint(0x3001);
+
+            interrupt.store(true, Ordering::SeqCst);
+            assert_eq!(vm.resume(), Ok(Stop::Interrupted));
+            assert!(!interrupt.load(Ordering::SeqCst));
+            assert_eq!(vm.registers().get(Register::PC), 0x3000);
+
+            assert_eq!(vm.resume(), Ok(Stop::Breakpoint(0x3001)));
+            assert_eq!(vm.registers().get(Register::R0), 1);
+            assert_eq!(vm.registers().get(Register::PC), 0x3001);
+
+            assert_eq!(vm.resume(), Ok(Stop::Halted));
+            assert_eq!(vm.registers().get(Register::R0), 2);
+        }
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut memory = Memory::default();
+        // JSR  #1
+        memory.write(0x3000, 0b0100_1_00000000001);
+        // HALT
+        memory.write(0x3001, 0xF025);
+        // JSR  #1
+        memory.write(0x3002, 0b0100_1_00000000001);
+        // RET
+        memory.write(0x3003, 0b1100_000_111_000000);
+        // RET
+        memory.write(0x3004, 0b1100_000_111_000000);
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.track_calls();
+        vm.add_breakpoint(0x3004);
+
+        assert_eq!(vm.resume(), Ok(Stop::Breakpoint(0x3004)));
+        assert_eq!(
+            vm.call_stack(),
+            [
+                Call {
+                    entry: 0x3002,
+                    return_addr: 0x3001
+                },
+                Call {
+                    entry: 0x3004,
+                    return_addr: 0x3003
+                }
+            ]
+        );
+
+        vm.step().unwrap();
+        assert_eq!(vm.call_stack().len(), 1);
+        // R7 still holds the inner return address, so this RET returns to no call.
+        vm.step().unwrap();
+        assert_eq!(vm.call_stack().len(), 1);
+        assert_eq!(vm.registers().get(Register::PC), 0x3003);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut memory = Memory::default();
+        // HALT
+        memory.write(0x3000, 0xF025);
+        // ADD  R0, R0, R1
+        memory.write(0x3001, 0b0001_000_000_0_00_001);
+        // RET
+        memory.write(0x3002, 0b1100_000_111_000000);
+        // ST   R7, #3
+        memory.write(0x3003, 0b0011_111_000000011);
+        // JSR  #-4
+        memory.write(0x3004, 0b0100_1_11111111100);
+        // LD   R7, #1
+        memory.write(0x3005, 0b0010_111_000000001);
+        // RET
+        memory.write(0x3006, 0b1100_000_111_000000);
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_register(Register::R0, 2);
+        vm.set_register(Register::R1, 3);
+
+        // The inner JSR returns to x3005, not the sentinel, so only the outer RET stops.
+        assert_eq!(vm.call(0x3003, RETURN_SENTINEL), Ok(Stop::Returned));
+        assert_eq!(vm.registers().get(Register::R0), 5);
+        assert_eq!(vm.registers().get(Register::PC), RETURN_SENTINEL);
+        assert_eq!(vm.breakpoints().count(), 0);
+
+        assert_eq!(vm.call(0x3000, RETURN_SENTINEL), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn test_user_mode_access_control_violation() {
+        let mut symbols = SymbolTable::default();
+        symbols.insert("MAIN", 0x3000);
+
+        for engine in [Engine::Interpreter, Engine::Blocks] {
+            let mut memory = Memory::default();
+            // STR  R0, R1, #0
+            memory.write(0x3000, 0b0111_000_001_000000);
+            // LDI  R2, #0
+            memory.write(0x3001, 0b1010_010_000000000);
+            memory.write(0x3002, 0xFE00);
+
+            let mut vm = Vm::new(Registers::default(), memory);
+            vm.set_symbols(symbols.clone());
+            vm.set_engine(engine);
+            vm.set_psr(0x8002).unwrap();
+            vm.set_register(Register::R0, 0xBEEF);
+            vm.set_register(Register::R1, 0x0100);
+
+            // The wild store into the interrupt vector table faults before it happens.
+            assert_eq!(
+                vm.run(),
+                Err(
+                    "access control violation at MAIN (STR R0, R1, #0): user-mode write to x0100"
+                        .to_string()
+                )
+            );
+            assert_eq!(vm.registers().get(Register::PC), 0x3000);
+            assert_eq!(vm.memory().peek(0x0100), 0);
+
+            vm.set_register(Register::R1, 0x4000);
+            assert_eq!(
+                vm.run(),
+                Err("access control violation at MAIN+1 (LDI R2, MAIN+2): user-mode read from xFE00".to_string())
+            );
+            assert_eq!(vm.registers().get(Register::R2), 0);
+
+            vm.set_pc(0x0200);
+            assert!(vm
+                .run()
+                .unwrap_err()
+                .ends_with("user-mode execution at x0200"));
+        }
+    }
+
+    #[test]
+    fn test_protection_map() {
+        let mut memory = Memory::default();
+        // STR  R0, R1, #0
+        memory.write(0x3000, 0b0111_000_001_000000);
+        // JMP  R1
+        memory.write(0x3001, 0b1100_000_001_000000);
+        // HALT
+        memory.write(0x3002, 0xF025);
+
+        let mut protection = ProtectionMap::default();
+        protection
+            .protect(0x3000, 0x3002, Protection::ReadOnly)
+            .unwrap();
+        protection
+            .protect(0x4000, 0x40FF, Protection::NoExecute)
+            .unwrap();
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_protection(protection);
+        vm.set_register(Register::R1, 0x3002);
+
+        assert_eq!(
+            vm.run(),
+            Err(
+                "protection violation at x3000 (STR R0, R1, #0): write to read-only x3002"
+                    .to_string()
+            )
+        );
+
+        // Storing to x4000 is allowed, but jumping there is not.
+        vm.set_register(Register::R1, 0x4000);
+        assert_eq!(
+            vm.run(),
+            Err(
+                "protection violation at x4000 (BR x4001): execution at no-execute x4000"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_starts_at_image_origin() {
+        let mut memory = Memory::default();
+        memory.load(&Image {
+            origin: 0x4000,
+            // ADD R0, R0, #1; HALT
+            words: vec![0b0001_000_000_1_00001, 0xF025],
+        });
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        assert_eq!(vm.registers().get(Register::PC), 0x4000);
+
+        vm.run().unwrap();
+        assert_eq!(vm.registers().get(Register::R0), 1);
+    }
+
+    #[test]
+    fn test_illegal_instruction_reports_location() {
+        let mut memory = Memory::default();
+        memory.write(0x3000, 0xD000);
+        let mut symbols = SymbolTable::default();
+        symbols.insert("MAIN", 0x3000);
+
+        let mut vm = Vm::new(Registers::default(), memory);
+        vm.set_symbols(symbols);
+
+        assert_eq!(
+            vm.run(),
+            Err("unsupported opcode RES at MAIN (xD000)".to_string())
+        );
+    }
 }
@@ -0,0 +1,377 @@
+//! Property tests of instruction semantics against a reference model of the LC-3, written
+//! from the ISA specification rather than from the VM's instruction types.
+//!
+//! The model follows the VM where it departs from the hardware: traps run natively instead
+//! of through the trap vector table, and `LEA` leaves the condition codes alone.
+#![cfg(feature = "std")]
+
+use std::{cell::RefCell, collections::VecDeque, io, rc::Rc};
+
+use lc3_vm::{
+    console::Scripted,
+    instructions::Instruction,
+    memory::Memory,
+    registers::{Register, Registers},
+};
+use proptest::prelude::*;
+
+const KBSR: u16 = 0xFE00;
+const KBDR: u16 = 0xFE02;
+
+const N: u16 = 0b100;
+const Z: u16 = 0b010;
+const P: u16 = 0b001;
+
+/// A generated starting state, with memory kept as the seed it expands from so that
+/// failure reports and saved regressions stay small.
+#[derive(Debug, Clone)]
+struct Start {
+    r: [u16; 8],
+    pc: u16,
+    cond: u16,
+    seed: u64,
+    keys: VecDeque<u8>,
+}
+
+impl Start {
+    fn machine(&self) -> Machine {
+        Machine {
+            r: self.r,
+            pc: self.pc,
+            cond: self.cond,
+            memory: memory(self.seed),
+            keys: self.keys.clone(),
+            output: String::new(),
+        }
+    }
+}
+
+/// The machine state the model runs on.
+#[derive(Clone, PartialEq)]
+struct Machine {
+    r: [u16; 8],
+    pc: u16,
+    cond: u16,
+    memory: Vec<u16>,
+    keys: VecDeque<u8>,
+    output: String,
+}
+
+/// What executing a word did, besides changing the state.
+#[derive(Debug, PartialEq)]
+enum Effect {
+    Executed,
+    Halted,
+    /// `RTI`, the reserved opcode or a trap vector without a service routine.
+    Illegal,
+}
+
+fn sext(value: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    (((value << shift) as i16) >> shift) as u16
+}
+
+impl Machine {
+    /// Reading the keyboard status register polls the keyboard, latching a key in KBDR.
+    fn read(&mut self, addr: u16) -> u16 {
+        if addr == KBSR {
+            match self.keys.pop_front() {
+                Some(key) if key != 0 => {
+                    self.memory[KBSR as usize] = 0x8000;
+                    self.memory[KBDR as usize] = key as u16;
+                }
+                _ => self.memory[KBSR as usize] = 0,
+            }
+        }
+        self.memory[addr as usize]
+    }
+
+    fn setcc(&mut self, value: u16) {
+        self.cond = match value as i16 {
+            v if v < 0 => N,
+            0 => Z,
+            _ => P,
+        };
+    }
+
+    /// Executes `ir` as if fetched from the PC.
+    fn step(&mut self, ir: u16) -> Effect {
+        let dr = ((ir >> 9) & 7) as usize;
+        let sr1 = ((ir >> 6) & 7) as usize;
+        let offset9 = sext(ir & 0x1FF, 9);
+        self.pc = self.pc.wrapping_add(1);
+
+        match ir >> 12 {
+            // BR
+            0b0000 => {
+                let nzp = (ir >> 9) & 7;
+                if nzp & self.cond != 0 {
+                    self.pc = self.pc.wrapping_add(offset9);
+                }
+            }
+            // ADD and AND
+            0b0001 | 0b0101 => {
+                let operand = if ir & 0x20 != 0 {
+                    sext(ir & 0x1F, 5)
+                } else {
+                    self.r[(ir & 7) as usize]
+                };
+                self.r[dr] = if ir >> 12 == 0b0001 {
+                    self.r[sr1].wrapping_add(operand)
+                } else {
+                    self.r[sr1] & operand
+                };
+                self.setcc(self.r[dr]);
+            }
+            // LD
+            0b0010 => {
+                self.r[dr] = self.read(self.pc.wrapping_add(offset9));
+                self.setcc(self.r[dr]);
+            }
+            // ST
+            0b0011 => self.memory[self.pc.wrapping_add(offset9) as usize] = self.r[dr],
+            // JSR and JSRR
+            0b0100 => {
+                let target = if ir & 0x800 != 0 {
+                    self.pc.wrapping_add(sext(ir & 0x7FF, 11))
+                } else {
+                    self.r[sr1]
+                };
+                self.r[7] = self.pc;
+                self.pc = target;
+            }
+            // LDR
+            0b0110 => {
+                self.r[dr] = self.read(self.r[sr1].wrapping_add(sext(ir & 0x3F, 6)));
+                self.setcc(self.r[dr]);
+            }
+            // STR
+            0b0111 => {
+                self.memory[self.r[sr1].wrapping_add(sext(ir & 0x3F, 6)) as usize] = self.r[dr]
+            }
+            // NOT
+            0b1001 => {
+                self.r[dr] = !self.r[sr1];
+                self.setcc(self.r[dr]);
+            }
+            // LDI
+            0b1010 => {
+                let pointer = self.read(self.pc.wrapping_add(offset9));
+                self.r[dr] = self.read(pointer);
+                self.setcc(self.r[dr]);
+            }
+            // STI
+            0b1011 => {
+                let pointer = self.read(self.pc.wrapping_add(offset9));
+                self.memory[pointer as usize] = self.r[dr];
+            }
+            // JMP and RET
+            0b1100 => self.pc = self.r[sr1],
+            // LEA
+            0b1110 => self.r[dr] = self.pc.wrapping_add(offset9),
+            // TRAP
+            0b1111 => return self.trap(ir & 0xFF),
+            // RTI and the reserved opcode
+            _ => return Effect::Illegal,
+        }
+
+        Effect::Executed
+    }
+
+    fn trap(&mut self, vector: u16) -> Effect {
+        if !(0x20..=0x25).contains(&vector) {
+            return Effect::Illegal;
+        }
+        self.r[7] = self.pc;
+
+        match vector {
+            // GETC
+            0x20 => self.r[0] = self.keys.pop_front().unwrap_or(0) as u16,
+            // OUT
+            0x21 => self.output.push((self.r[0] & 0xFF) as u8 as char),
+            // PUTS: one character per word, stopping after one pass over memory
+            0x22 => {
+                for i in 0..=0xFFFF {
+                    let c = self.read(self.r[0].wrapping_add(i)) & 0xFF;
+                    if c == 0 {
+                        break;
+                    }
+                    self.output.push(c as u8 as char);
+                }
+            }
+            // IN
+            0x23 => {
+                self.output.push_str("Enter a  character : ");
+                self.r[0] = self.keys.pop_front().unwrap_or(0) as u16;
+            }
+            // PUTSP: two characters per word, low byte first
+            0x24 => {
+                for i in 0..=0xFFFF {
+                    let word = self.read(self.r[0].wrapping_add(i));
+                    if word == 0 {
+                        break;
+                    }
+                    self.output.push((word & 0xFF) as u8 as char);
+                    if word >> 8 != 0 {
+                        self.output.push((word >> 8) as u8 as char);
+                    }
+                }
+            }
+            // HALT
+            _ => return Effect::Halted,
+        }
+
+        Effect::Executed
+    }
+}
+
+/// Output the test can still read after handing it to the console.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `ir` from `state` through [`Instruction::execute`], with the PC already past it as
+/// the VM leaves it after fetching. `None` if the word does not decode.
+fn execute(state: &Machine, ir: u16) -> Option<Machine> {
+    let instruction = Instruction::try_from(ir).ok()?;
+
+    let mut memory = Memory::default();
+    for (addr, &word) in state.memory.iter().enumerate() {
+        memory.write(addr as u16, word);
+    }
+    let output = Capture::default();
+    let keys: Vec<u8> = state.keys.iter().copied().collect();
+    memory.set_console(Box::new(Scripted::new(keys, output.clone())));
+
+    let mut registers = Registers::default();
+    for (n, &value) in state.r.iter().enumerate() {
+        registers.set(Register::from_bits(n as u16), value);
+    }
+    registers.set(Register::PC, state.pc.wrapping_add(1));
+    registers.set(Register::COND, state.cond);
+
+    instruction.execute(&mut registers, &mut memory);
+
+    let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+    Some(Machine {
+        r: std::array::from_fn(|n| registers.get(Register::from_bits(n as u16))),
+        pc: registers.get(Register::PC),
+        cond: registers.get(Register::COND),
+        memory: (0..=0xFFFF).map(|addr| memory.peek(addr)).collect(),
+        // Scripted does not say which keys are left; those read show in R0, KBDR or output.
+        keys: VecDeque::new(),
+        output,
+    })
+}
+
+/// A full memory image from `seed`, about a quarter of it zeros so that strings end.
+fn memory(seed: u64) -> Vec<u16> {
+    let mut x = seed | 1;
+    (0..0x10000)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            if x & 3 == 0 {
+                0
+            } else {
+                (x >> 16) as u16
+            }
+        })
+        .collect()
+}
+
+prop_compose! {
+    fn start()(
+        r in any::<[u16; 8]>(),
+        pc in any::<u16>(),
+        cond in prop::sample::select(vec![N, Z, P]),
+        seed in any::<u64>(),
+        keys in prop::collection::vec_deque(any::<u8>(), 0..4),
+    ) -> Start {
+        Start { r, pc, cond, seed, keys }
+    }
+}
+
+/// Any word, with extra weight on the trap vectors that have service routines.
+fn word() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        4 => any::<u16>(),
+        1 => (0x20u16..=0x25).prop_map(|vector| 0xF000 | vector),
+    ]
+}
+
+fn check(start: &Start, ir: u16) -> Result<(), TestCaseError> {
+    let state = start.machine();
+    let mut model = state.clone();
+    model.memory[state.pc as usize] = ir;
+    let effect = model.step(ir);
+
+    let decoded = Instruction::try_from(ir);
+    match effect {
+        Effect::Illegal => {
+            prop_assert!(decoded.is_err(), "x{:04X} decoded as {:?}", ir, decoded);
+            return Ok(());
+        }
+        // Executing HALT only saves the return address; stopping is up to the VM.
+        Effect::Halted => {
+            prop_assert!(decoded.map(|instruction| instruction.is_halt()) == Ok(true));
+        }
+        Effect::Executed => {}
+    }
+
+    let mut vm_state = state;
+    vm_state.memory[vm_state.pc as usize] = ir;
+    let actual = execute(&vm_state, ir).unwrap();
+
+    prop_assert_eq!(actual.r, model.r, "registers after x{:04X}", ir);
+    prop_assert_eq!(actual.pc, model.pc, "PC after x{:04X}", ir);
+    prop_assert_eq!(actual.cond, model.cond, "NZP after x{:04X}", ir);
+    prop_assert_eq!(actual.output, model.output, "output after x{:04X}", ir);
+    if let Some(addr) = (0..=0xFFFF).find(|&addr| actual.memory[addr] != model.memory[addr]) {
+        prop_assert!(
+            false,
+            "x{:04X}: x{:04X} in the VM, x{:04X} in the model, after x{:04X}",
+            addr,
+            actual.memory[addr],
+            model.memory[addr],
+            ir
+        );
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1024))]
+
+    #[test]
+    fn instructions_match_the_reference_model(state in start(), ir in word()) {
+        check(&state, ir)?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    /// Every opcode, each with random operands, so that none is left to chance.
+    #[test]
+    fn every_opcode_matches_the_reference_model(
+        state in start(),
+        operands in any::<u16>(),
+    ) {
+        for opcode in 0..16 {
+            check(&state, opcode << 12 | operands & 0x0FFF)?;
+        }
+    }
+}